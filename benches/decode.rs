@@ -0,0 +1,108 @@
+//! `cargo bench --bench decode`
+//!
+//! Compares the single-pass `WithingsResponse<T>` decoding with the former
+//! path, which decoded into `serde_json::Value` before converting to `T`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use serde::Deserialize;
+use serde_json::json;
+use std::hint::black_box;
+use withings_api::error::RequestMeta;
+use withings_api::response::decode;
+
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct Measure {
+    value: i64,
+    #[serde(rename = "type")]
+    meastype: u64,
+    unit: i64,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct MeasureGroup {
+    grpid: u64,
+    date: u64,
+    created: u64,
+    category: u64,
+    deviceid: String,
+    measures: Vec<Measure>,
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize)]
+struct MeasureBody {
+    timezone: String,
+    measuregrps: Vec<MeasureGroup>,
+    more: u64,
+    offset: u64,
+}
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    body: T,
+}
+
+fn fixture(groups: u64) -> Vec<u8> {
+    let measuregrps = (0..groups)
+        .map(|i| {
+            json!({
+                "grpid": 1_000_000 + i,
+                "attrib": 0,
+                "date": 1_600_000_000 + i * 60,
+                "created": 1_600_000_030 + i * 60,
+                "category": 1,
+                "deviceid": "cc50f32653df14137da15aaaaa7b2e07",
+                "hash_deviceid": "f32bbbb318f14137da157b2e07",
+                "measures": [
+                    {"value": 80000 + i % 100, "type": 1, "unit": -3, "algo": 3, "fm": 131},
+                    {"value": 120 + i % 10, "type": 10, "unit": 0, "algo": 0, "fm": 3},
+                    {"value": 80 + i % 10, "type": 9, "unit": 0, "algo": 0, "fm": 3}
+                ],
+                "comment": null
+            })
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_vec(&json!({
+        "status": 0,
+        "body": {
+            "updatetime": 1644138861,
+            "timezone": "Asia/Tokyo",
+            "measuregrps": measuregrps,
+            "more": 0,
+            "offset": 0
+        }
+    }))
+    .unwrap()
+}
+
+fn two_pass(bytes: &[u8]) -> MeasureBody {
+    let value: serde_json::Value = serde_json::from_slice(bytes).unwrap();
+    assert_eq!(value["status"].as_u64(), Some(0));
+    serde_json::from_value::<Envelope<MeasureBody>>(value)
+        .unwrap()
+        .body
+}
+
+fn single_pass(bytes: &[u8]) -> MeasureBody {
+    let meta = RequestMeta::new("https://wbsapi.withings.net/measure", "getmeas");
+    decode(meta, bytes).unwrap()
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let bytes = fixture(20_000);
+
+    let mut group = c.benchmark_group("decode_getmeas");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.sample_size(20);
+    group.bench_function("two_pass_value", |b| b.iter(|| two_pass(black_box(&bytes))));
+    group.bench_function("single_pass_envelope", |b| {
+        b.iter(|| single_pass(black_box(&bytes)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
@@ -17,7 +17,8 @@ async fn main() {
         std::env::var("CONSUMER_SECRET").expect("CONSUMER_SECRET must be present."),
         std::env::var("CALLBACK_URL").expect("CALLBACK_URL must be present."),
         vec![Scope::UserInfo, Scope::UserMetrics],
-        Some("demo".into()),
+        // `Some("demo".into())` logs in to the Withings demo account instead.
+        None,
     );
 
     let res = client
@@ -26,4 +27,5 @@ async fn main() {
         .unwrap_or_else(|e| panic!("error {}", e));
 
     println!("Response {:?}", res);
+    println!("ACCSESS_TOKEN={}", res.access_token.expose());
 }
@@ -3,7 +3,7 @@
 
 use dotenv::dotenv;
 use withings_api::{
-    auth::cli::{AuthCli, Scope},
+    auth::cli::{AuthCli, Scope, State},
     WITHINGS_API_URL,
 };
 
@@ -17,12 +17,15 @@ async fn main() {
         std::env::var("CONSUMER_SECRET").expect("CONSUMER_SECRET must be present."),
         std::env::var("CALLBACK_URL").expect("CALLBACK_URL must be present."),
         vec![Scope::UserInfo, Scope::UserMetrics],
-        Some("demo".into()),
+        // `Some("demo".into())` logs in to the Withings demo account instead.
+        None,
     );
 
+    let state = State::random();
     let res = client
-        .get_authorize_url()
+        .get_authorize_url(&state)
         .unwrap_or_else(|e| panic!("error {}", e));
 
     println!("Response {:?}", res);
+    println!("State {}", state.as_str());
 }
@@ -0,0 +1,31 @@
+//! `cargo run --example login --features=env`
+#![deny(warnings)]
+
+use dotenv::dotenv;
+use withings_api::{
+    auth::cli::{AuthCli, Scope},
+    auth::login::open_authorize_url,
+    WITHINGS_API_URL,
+};
+
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
+
+    let client = AuthCli::new(
+        WITHINGS_API_URL.into(),
+        std::env::var("CLIENT_ID").expect("CLIENT_ID must be present."),
+        std::env::var("CONSUMER_SECRET").expect("CONSUMER_SECRET must be present."),
+        std::env::var("CALLBACK_URL").expect("CALLBACK_URL must be present."),
+        vec![Scope::UserInfo, Scope::UserMetrics],
+        None,
+    );
+
+    let res = client
+        .login_with_loopback(open_authorize_url)
+        .await
+        .unwrap_or_else(|e| panic!("error {}", e));
+
+    println!("Response {:?}", res);
+    println!("ACCSESS_TOKEN={}", res.access_token.expose());
+}
@@ -1 +1,7 @@
+pub mod activity;
 pub mod cli;
+pub mod intraday;
+pub mod measure;
+pub mod sleep;
+pub mod user;
+pub mod workout;
@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+
+use crate::de::bool_or_number;
+use crate::params::{comma_separated, YmdDate};
+use crate::response::WithingsResponse;
+
+pub(crate) const MEASURE_V2_PATH: &str = "/v2/measure";
+
+/// `getactivity`: daily activity aggregates. Either a date range or
+/// `lastupdate` must be set.
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct GetActivityRequest {
+    pub startdateymd: Option<YmdDate>,
+    pub enddateymd: Option<YmdDate>,
+    pub lastupdate: Option<u64>,
+    pub offset: Option<u64>,
+    #[serde(serialize_with = "comma_separated")]
+    pub data_fields: Option<Vec<String>>,
+}
+
+pub type GetActivityResponse = WithingsResponse<ActivityBody>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ActivityBody {
+    pub activities: Vec<Activity>,
+    #[serde(default, deserialize_with = "bool_or_number")]
+    pub more: bool,
+    #[serde(default)]
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Activity {
+    pub date: YmdDate,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub deviceid: Option<String>,
+    #[serde(default)]
+    pub hash_deviceid: Option<String>,
+    #[serde(default)]
+    pub brand: Option<u64>,
+    #[serde(default)]
+    pub is_tracker: Option<bool>,
+    #[serde(default)]
+    pub steps: Option<u64>,
+    #[serde(default)]
+    pub distance: Option<f64>,
+    #[serde(default)]
+    pub elevation: Option<f64>,
+    #[serde(default)]
+    pub soft: Option<u64>,
+    #[serde(default)]
+    pub moderate: Option<u64>,
+    #[serde(default)]
+    pub intense: Option<u64>,
+    #[serde(default)]
+    pub active: Option<u64>,
+    #[serde(default)]
+    pub calories: Option<f64>,
+    #[serde(default)]
+    pub totalcalories: Option<f64>,
+    #[serde(default)]
+    pub hr_average: Option<u64>,
+    #[serde(default)]
+    pub hr_min: Option<u64>,
+    #[serde(default)]
+    pub hr_max: Option<u64>,
+    #[serde(default)]
+    pub hr_zone_0: Option<u64>,
+    #[serde(default)]
+    pub hr_zone_1: Option<u64>,
+    #[serde(default)]
+    pub hr_zone_2: Option<u64>,
+    #[serde(default)]
+    pub hr_zone_3: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_get_activity_request() -> anyhow::Result<()> {
+        let req = GetActivityRequest {
+            startdateymd: Some(YmdDate::new(2022, 2, 1)),
+            enddateymd: Some(YmdDate::new(2022, 2, 4)),
+            data_fields: Some(vec!["steps".into(), "calories".into()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_urlencoded::to_string(&req)?,
+            "startdateymd=2022-02-01&enddateymd=2022-02-04&data_fields=steps%2Ccalories"
+        );
+
+        let req = GetActivityRequest {
+            lastupdate: Some(1644138861),
+            offset: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_urlencoded::to_string(&req)?,
+            "lastupdate=1644138861&offset=2"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_activity() -> anyhow::Result<()> {
+        let body: ActivityBody = serde_json::from_value(json!({
+            "activities": [{
+                "date": "2022-02-04",
+                "timezone": "Asia/Tokyo",
+                "deviceid": null,
+                "brand": 18,
+                "is_tracker": true,
+                "steps": 8123,
+                "distance": 5934.4,
+                "elevation": 12.3,
+                "calories": 312.5,
+                "totalcalories": 2203.1,
+                "hr_average": 72
+            }],
+            "more": false,
+            "offset": 0
+        }))?;
+
+        assert!(!body.more);
+        let activity = &body.activities[0];
+        assert_eq!(activity.date, YmdDate::new(2022, 2, 4));
+        assert_eq!(activity.steps, Some(8123));
+        assert_eq!(activity.deviceid, None);
+        assert_eq!(activity.hr_min, None);
+        Ok(())
+    }
+}
@@ -1,55 +1,176 @@
 use std::fmt::Debug;
 
+use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::error::handle_response;
-
-const MEASURE_PATH: &str = "/measure";
+pub use crate::api::activity::GetActivityRequest;
+use crate::api::activity::{ActivityBody, MEASURE_V2_PATH};
+pub use crate::api::intraday::GetIntradayActivityRequest;
+use crate::api::intraday::IntradayActivityBody;
+pub use crate::api::measure::{Category, GetMeasRequest, MeasureType};
+use crate::api::measure::{MeasureBody, MEASURE_PATH};
+pub use crate::api::sleep::{GetSleepRequest, GetSleepSummaryRequest};
+use crate::api::sleep::{SleepBody, SleepSummaryBody, SLEEP_V2_PATH};
+use crate::api::user::{DevicesBody, USER_V2_PATH};
+pub use crate::api::workout::GetWorkoutsRequest;
+use crate::api::workout::WorkoutsBody;
+use crate::error::RequestMeta;
+use crate::rate_limit::RateLimiter;
+use crate::retry::{send_with_retry, Dispatch, RetryPolicy};
+use crate::secret::Secret;
+use crate::Result;
 
 #[derive(Debug, Clone)]
 pub struct ApiCli {
-    access_token: String,
+    access_token: Secret,
     base_url: String,
     client: reqwest::Client,
-}
-
-#[derive(Debug, Clone, PartialEq, Serialize, Default)]
-pub struct GetMeasRequest {
-    pub action: String,        // TODO: enum
-    pub meastype: Option<u64>, // TODO: enum
-    pub meastypes: Option<Vec<u64>>,
-    pub category: Option<u64>, // TODO: enum
-    pub startdate: Option<u64>,
-    pub enddate: Option<u64>,
-    pub offset: Option<u64>,
-    pub lastupdate: Option<u64>,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+    user_id: Option<u64>,
+    strict: bool,
 }
 
 impl ApiCli {
     pub fn new(access_token: String, base_url: String) -> ApiCli {
         ApiCli {
-            access_token,
+            access_token: access_token.into(),
             base_url,
             client: reqwest::Client::new(),
+            retry_policy: RetryPolicy::none(),
+            rate_limiter: None,
+            user_id: None,
+            strict: false,
+        }
+    }
+
+    pub fn with_client(access_token: String, base_url: String, client: reqwest::Client) -> ApiCli {
+        ApiCli {
+            access_token: access_token.into(),
+            base_url,
+            client,
+            retry_policy: RetryPolicy::none(),
+            rate_limiter: None,
+            user_id: None,
+            strict: false,
         }
     }
 
-    pub async fn get_meas(&self, req: &GetMeasRequest) -> anyhow::Result<serde_json::value::Value> {
-        let res = self
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> ApiCli {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> ApiCli {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Withings user owning the access token, used for per-user rate limits.
+    pub fn with_user_id(mut self, user_id: u64) -> ApiCli {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    /// Fail with `Error::UnknownFields` when a response has fields the
+    /// target type doesn't map, to detect API drift.
+    pub fn with_strict(mut self, strict: bool) -> ApiCli {
+        self.strict = strict;
+        self
+    }
+
+    fn dispatch(&self, idempotent: bool) -> Dispatch<'_> {
+        Dispatch {
+            retry_policy: &self.retry_policy,
+            rate_limiter: self.rate_limiter.as_ref(),
+            user_id: self.user_id,
+            idempotent,
+            strict: self.strict,
+        }
+    }
+
+    /// Call any action of any Withings service, e.g. one this crate doesn't
+    /// support yet. `params` must serialize to a flat form; an `action` field
+    /// in it is replaced by `action`. `T` can be `serde_json::Value`.
+    pub async fn call_raw<T, P>(&self, service_path: &str, action: &str, params: &P) -> Result<T>
+    where
+        T: DeserializeOwned,
+        P: Serialize + ?Sized,
+    {
+        let encoded = serde_urlencoded::to_string(params)?;
+        let form = std::iter::once(("action".to_string(), action.to_string()))
+            .chain(
+                url::form_urlencoded::parse(encoded.as_bytes())
+                    .into_owned()
+                    .filter(|(k, _)| k != "action"),
+            )
+            .collect::<Vec<_>>();
+
+        let url = format!("{}{}", &self.base_url, service_path);
+        let meta = RequestMeta::new(&url, action);
+        let req = self
             .client
-            .post(format!("{}{}", &self.base_url, MEASURE_PATH))
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .form(&req)
-            .send()
-            .await?;
+            .post(url)
+            .header(
+                "Authorization",
+                format!("Bearer {}", self.access_token.expose()),
+            )
+            .form(&form);
+
+        send_with_retry(self.dispatch(is_read_action(action)), meta, req).await
+    }
+
+    pub async fn get_meas(&self, req: &GetMeasRequest) -> Result<MeasureBody> {
+        self.call_raw(MEASURE_PATH, &req.action, req).await
+    }
+
+    pub async fn get_activity(&self, req: &GetActivityRequest) -> Result<ActivityBody> {
+        self.call_raw(MEASURE_V2_PATH, "getactivity", req).await
+    }
 
-        Ok(handle_response(req, res).await?)
+    pub async fn get_intraday_activity(
+        &self,
+        req: &GetIntradayActivityRequest,
+    ) -> Result<IntradayActivityBody> {
+        self.call_raw(MEASURE_V2_PATH, "getintradayactivity", req)
+            .await
     }
+
+    pub async fn get_sleep(&self, req: &GetSleepRequest) -> Result<SleepBody> {
+        self.call_raw(SLEEP_V2_PATH, "get", req).await
+    }
+
+    pub async fn get_sleep_summary(
+        &self,
+        req: &GetSleepSummaryRequest,
+    ) -> Result<SleepSummaryBody> {
+        self.call_raw(SLEEP_V2_PATH, "getsummary", req).await
+    }
+
+    pub async fn get_workouts(&self, req: &GetWorkoutsRequest) -> Result<WorkoutsBody> {
+        self.call_raw(MEASURE_V2_PATH, "getworkouts", req).await
+    }
+
+    /// Devices linked to the user.
+    pub async fn get_devices(&self) -> Result<DevicesBody> {
+        self.call_raw(USER_V2_PATH, "getdevice", &()).await
+    }
+}
+
+/// Reads are safe to retry; anything else, e.g. subscribing to
+/// notifications, is not.
+fn is_read_action(action: &str) -> bool {
+    action.starts_with("get") || action.starts_with("list")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::api::measure::{Measure, MeasureGroup};
+    use crate::error::WithingsApiError;
+    use crate::params::YmdDate;
+    use crate::Error;
+    use assert_matches::assert_matches;
     use serde_json::json;
 
     #[tokio::test]
@@ -58,9 +179,9 @@ mod tests {
 
         let req = GetMeasRequest {
             action: "getmeas".into(),
-            meastype: Some(1),
-            meastypes: None,
-            category: Some(1),
+            meastype: Some(MeasureType::Weight),
+            meastypes: Some(vec![MeasureType::Weight, MeasureType::FatRatio]),
+            category: Some(Category::Real),
             startdate: Some(1),
             enddate: Some(12345),
             offset: Some(1),
@@ -107,9 +228,470 @@ mod tests {
             .create();
 
         let res = client.get_meas(&req).await?;
-        assert_eq!(res, response_body);
+        assert_eq!(
+            res,
+            MeasureBody {
+                updatetime: 1644138861,
+                timezone: Some("Asia/Tokyo".into()),
+                measuregrps: vec![MeasureGroup {
+                    grpid: 123456789,
+                    attrib: 0,
+                    date: 1643969671,
+                    created: 1643969717,
+                    modified: None,
+                    category: Category::Real,
+                    deviceid: Some("cc50f32653df14137da15aaaaa7b2e07".into()),
+                    hash_deviceid: Some("f32bbbb318f14137da157b2e07".into()),
+                    measures: vec![Measure {
+                        value: 80000,
+                        meastype: MeasureType::Weight,
+                        unit: -3,
+                        algo: Some(3),
+                        fm: Some(131),
+                        apppfmid: Some(7),
+                        appliver: Some(5080201),
+                    }],
+                    comment: Some("test comment".into()),
+                }],
+                more: 1,
+                offset: 0,
+            }
+        );
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_meas_too_many_requests() -> anyhow::Result<()> {
+        let client = ApiCli::new("access_token".into(), mockito::server_url());
+
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            ..Default::default()
+        };
+
+        let mock = mockito::mock("POST", MEASURE_PATH)
+            .with_status(200)
+            .with_body(serde_json::to_string(&json!({
+                "status": 601,
+                "body": {},
+                "error": "Too Many Requests"
+            }))?)
+            .create();
+
+        let res = client.get_meas(&req).await;
+        assert_matches!(
+            res,
+            Err(Error::Api(WithingsApiError::TooManyRequests { status: 601, error, .. })) => {
+                assert_eq!(error.as_deref(), Some("Too Many Requests"));
+            }
+        );
+        mock.assert();
+
+        Ok(())
+    }
+
+    fn retrying_client() -> ApiCli {
+        ApiCli::new("access_token".into(), mockito::server_url()).with_retry_policy(RetryPolicy {
+            initial_backoff: std::time::Duration::from_millis(1),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_get_meas_retries_transient_errors() -> anyhow::Result<()> {
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            ..Default::default()
+        };
+
+        let too_many_requests = mockito::mock("POST", MEASURE_PATH)
+            .with_status(200)
+            .with_body(serde_json::to_string(&json!({"status": 601, "body": {}}))?)
+            .expect(1)
+            .create();
+        let bad_gateway = mockito::mock("POST", MEASURE_PATH)
+            .with_status(502)
+            .expect(1)
+            .create();
+        let ok = mockito::mock("POST", MEASURE_PATH)
+            .with_status(200)
+            .with_body(serde_json::to_string(
+                &json!({"status": 0, "body": {"measuregrps": []}}),
+            )?)
+            .expect(1)
+            .create();
+
+        retrying_client().get_meas(&req).await?;
+        too_many_requests.assert();
+        bad_gateway.assert();
+        ok.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_meas_gives_up_after_max_attempts() -> anyhow::Result<()> {
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            ..Default::default()
+        };
+
+        let mock = mockito::mock("POST", MEASURE_PATH)
+            .with_status(200)
+            .with_body(serde_json::to_string(&json!({"status": 2, "body": {}}))?)
+            .expect(3)
+            .create();
+
+        let res = retrying_client().get_meas(&req).await;
+        assert_matches!(res, Err(Error::Api(WithingsApiError::Temporary { .. })));
         mock.assert();
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_get_meas_does_not_retry_permanent_errors() -> anyhow::Result<()> {
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            ..Default::default()
+        };
+
+        let mock = mockito::mock("POST", MEASURE_PATH)
+            .with_status(200)
+            .with_body(serde_json::to_string(&json!({"status": 503, "body": {}}))?)
+            .expect(1)
+            .create();
+
+        let res = retrying_client().get_meas(&req).await;
+        assert_matches!(res, Err(Error::Api(WithingsApiError::InvalidParams { .. })));
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_meas_honours_retry_after() -> anyhow::Result<()> {
+        use std::time::Duration;
+
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            ..Default::default()
+        };
+        let ok = serde_json::to_string(&json!({"status": 0, "body": {"measuregrps": []}}))?;
+
+        // Retry-After replaces a backoff that would outlast the test.
+        let client = ApiCli::new("access_token".into(), mockito::server_url()).with_retry_policy(
+            RetryPolicy {
+                initial_backoff: Duration::from_secs(3600),
+                max_backoff: Duration::from_secs(3600),
+                jitter: false,
+                ..Default::default()
+            },
+        );
+        let too_many_requests = mockito::mock("POST", MEASURE_PATH)
+            .with_status(429)
+            .with_header("retry-after", "0")
+            .expect(1)
+            .create();
+        let status_601 = mockito::mock("POST", MEASURE_PATH)
+            .with_status(200)
+            .with_header("retry-after", "0")
+            .with_body(serde_json::to_string(&json!({"status": 601, "body": {}}))?)
+            .expect(1)
+            .create();
+        let ok_mock = mockito::mock("POST", MEASURE_PATH)
+            .with_status(200)
+            .with_body(&ok)
+            .expect(1)
+            .create();
+        tokio::time::timeout(Duration::from_secs(10), client.get_meas(&req)).await??;
+        too_many_requests.assert();
+        status_601.assert();
+        ok_mock.assert();
+
+        // ... and is capped at max_backoff.
+        let client = ApiCli::new("access_token".into(), mockito::server_url()).with_retry_policy(
+            RetryPolicy {
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+        );
+        let unavailable = mockito::mock("POST", MEASURE_PATH)
+            .with_status(503)
+            .with_header("retry-after", "3600")
+            .expect(1)
+            .create();
+        let ok_mock = mockito::mock("POST", MEASURE_PATH)
+            .with_status(200)
+            .with_body(&ok)
+            .expect(1)
+            .create();
+        tokio::time::timeout(Duration::from_secs(10), client.get_meas(&req)).await??;
+        unavailable.assert();
+        ok_mock.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_get_meas_rate_limited() -> anyhow::Result<()> {
+        use crate::rate_limit::{RateLimit, RateLimitWait};
+        use std::sync::{Arc, Mutex};
+
+        let limiter = RateLimiter::new(RateLimit {
+            requests_per_minute: 1,
+            per_user_requests_per_minute: None,
+        });
+        let waits = Arc::new(Mutex::new(vec![]));
+        let recorded = waits.clone();
+        limiter.set_observer(move |w| recorded.lock().unwrap().push(w.clone()));
+
+        let client = ApiCli::new("access_token".into(), mockito::server_url())
+            .with_rate_limiter(limiter)
+            .with_user_id(363);
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            ..Default::default()
+        };
+
+        let mock = mockito::mock("POST", MEASURE_PATH)
+            .with_status(200)
+            .with_body(serde_json::to_string(
+                &json!({"status": 0, "body": {"measuregrps": []}}),
+            )?)
+            .expect(2)
+            .create();
+
+        client.get_meas(&req).await?;
+        client.get_meas(&req).await?;
+        mock.assert();
+
+        assert_eq!(
+            *waits.lock().unwrap(),
+            vec![RateLimitWait {
+                action: "getmeas".into(),
+                user_id: Some(363),
+                waited: std::time::Duration::from_secs(60),
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_meas_strict() -> anyhow::Result<()> {
+        let client = ApiCli::new("access_token".into(), mockito::server_url()).with_strict(true);
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            lastupdate: Some(1644138862),
+            ..Default::default()
+        };
+        let mock = mockito::mock("POST", MEASURE_PATH)
+            .match_body("action=getmeas&lastupdate=1644138862")
+            .with_status(200)
+            .with_body(serde_json::to_string(&json!({
+                "status": 0,
+                "body": {
+                    "measuregrps": [{
+                        "grpid": 1,
+                        "attrib": 0,
+                        "date": 1643969671,
+                        "created": 1643969717,
+                        "category": 1,
+                        "modelid": 5,
+                        "measures": []
+                    }]
+                }
+            }))?)
+            .create();
+
+        assert_matches!(
+            client.get_meas(&req).await,
+            Err(Error::UnknownFields { fields, .. }) => {
+                assert_eq!(fields, vec!["body.measuregrps.0.modelid"]);
+            }
+        );
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v2_endpoints() -> anyhow::Result<()> {
+        let client = ApiCli::new("access_token".into(), mockito::server_url());
+
+        let activity = mockito::mock("POST", MEASURE_V2_PATH)
+            .match_body("action=getactivity&lastupdate=1644138861")
+            .with_status(200)
+            .with_body(serde_json::to_string(&json!({
+                "status": 0,
+                "body": {"activities": [{"date": "2022-02-04", "steps": 8123}], "more": false, "offset": 0}
+            }))?)
+            .create();
+        let sleep = mockito::mock("POST", SLEEP_V2_PATH)
+            .match_body("action=getsummary&startdateymd=2022-02-01&enddateymd=2022-02-04")
+            .with_status(200)
+            .with_body(serde_json::to_string(
+                &json!({"status": 0, "body": {"series": [], "more": false, "offset": 0}}),
+            )?)
+            .create();
+        let workouts = mockito::mock("POST", MEASURE_V2_PATH)
+            .match_body("action=getworkouts&lastupdate=1644138861&offset=3")
+            .with_status(200)
+            .with_body(serde_json::to_string(
+                &json!({"status": 0, "body": {"series": [], "more": true, "offset": 3}}),
+            )?)
+            .create();
+
+        let res = client
+            .get_activity(&GetActivityRequest {
+                lastupdate: Some(1644138861),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(res.activities[0].steps, Some(8123));
+
+        let res = client
+            .get_sleep_summary(&GetSleepSummaryRequest {
+                startdateymd: Some(YmdDate::new(2022, 2, 1)),
+                enddateymd: Some(YmdDate::new(2022, 2, 4)),
+                ..Default::default()
+            })
+            .await?;
+        assert!(res.series.is_empty());
+
+        let res = client
+            .get_workouts(&GetWorkoutsRequest {
+                lastupdate: Some(1644138861),
+                offset: Some(3),
+                ..Default::default()
+            })
+            .await?;
+        assert!(res.more);
+
+        let devices = mockito::mock("POST", USER_V2_PATH)
+            .match_body("action=getdevice")
+            .with_status(200)
+            .with_body(serde_json::to_string(&json!({
+                "status": 0,
+                "body": {"devices": [{"type": "Scale", "model": "Body+", "deviceid": "test_device"}]}
+            }))?)
+            .create();
+
+        let res = client.get_devices().await?;
+        assert_eq!(res.devices[0].deviceid, "test_device");
+
+        let intraday = mockito::mock("POST", MEASURE_V2_PATH)
+            .match_body("action=getintradayactivity&startdate=1643932800&enddate=1644019200&data_fields=steps%2Cheart_rate")
+            .with_status(200)
+            .with_body(serde_json::to_string(
+                &json!({"status": 0, "body": {"series": {"1643969640": {"steps": 41}}}}),
+            )?)
+            .create();
+        let res = client
+            .get_intraday_activity(&GetIntradayActivityRequest {
+                startdate: Some(1643932800),
+                enddate: Some(1644019200),
+                data_fields: Some(vec!["steps".into(), "heart_rate".into()]),
+            })
+            .await?;
+        assert_eq!(res.series[&1643969640].steps, Some(41));
+
+        let sleep_series = mockito::mock("POST", SLEEP_V2_PATH)
+            .match_body("action=get&startdate=1643932800&enddate=1644019200")
+            .with_status(200)
+            .with_body(serde_json::to_string(
+                &json!({"status": 0, "body": {"series": []}}),
+            )?)
+            .create();
+        let res = client
+            .get_sleep(&GetSleepRequest {
+                startdate: Some(1643932800),
+                enddate: Some(1644019200),
+                ..Default::default()
+            })
+            .await?;
+        assert!(res.series.is_empty());
+
+        activity.assert();
+        sleep.assert();
+        workouts.assert();
+        devices.assert();
+        intraday.assert();
+        sleep_series.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_call_raw() -> anyhow::Result<()> {
+        let client = ApiCli::new("access_token".into(), mockito::server_url());
+
+        let response_body = json!({
+            "status": 0,
+            "body": {"devices": [{"type": "Scale", "deviceid": "test_device"}]}
+        });
+        let mock = mockito::mock("POST", "/v2/user")
+            .match_header("authorization", "Bearer access_token")
+            .match_body("action=getdevice&lang=en")
+            .with_status(200)
+            .with_body(serde_json::to_string(&response_body)?)
+            .create();
+
+        let res: serde_json::Value = client
+            .call_raw("/v2/user", "getdevice", &[("lang", "en")])
+            .await?;
+        assert_eq!(res, response_body["body"]);
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_call_raw_replaces_action() -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        struct Params {
+            action: &'static str,
+            userid: u64,
+        }
+
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Body {
+            more: bool,
+        }
+
+        let client = ApiCli::new("access_token".into(), mockito::server_url());
+        let mock = mockito::mock("POST", "/notify")
+            .match_body("action=list&userid=363")
+            .with_status(200)
+            .with_body(serde_json::to_string(
+                &json!({"status": 0, "body": {"more": false}}),
+            )?)
+            .create();
+
+        let res: Body = client
+            .call_raw(
+                "/notify",
+                "list",
+                &Params {
+                    action: "ignored",
+                    userid: 363,
+                },
+            )
+            .await?;
+        assert_eq!(res, Body { more: false });
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_read_action() {
+        assert!(is_read_action("getmeas"));
+        assert!(is_read_action("list"));
+        assert!(!is_read_action("subscribe"));
+        assert!(!is_read_action("revoke"));
+    }
 }
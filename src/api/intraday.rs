@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::de::{map_or_empty_array, option_number_or_string};
+use crate::params::comma_separated;
+use crate::response::WithingsResponse;
+
+/// `getintradayactivity`, sent to `/v2/measure`. Withings returns at most
+/// 24 hours per call.
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct GetIntradayActivityRequest {
+    pub startdate: Option<u64>,
+    pub enddate: Option<u64>,
+    #[serde(serialize_with = "comma_separated")]
+    pub data_fields: Option<Vec<String>>,
+}
+
+pub type GetIntradayActivityResponse = WithingsResponse<IntradayActivityBody>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct IntradayActivityBody {
+    /// Samples keyed by their epoch timestamp.
+    #[serde(default, deserialize_with = "map_or_empty_array")]
+    pub series: BTreeMap<u64, IntradaySample>,
+}
+
+/// Fields present depend on the device and on `data_fields`.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct IntradaySample {
+    pub deviceid: Option<String>,
+    pub model: Option<String>,
+    #[serde(deserialize_with = "option_number_or_string")]
+    pub model_id: Option<u64>,
+    pub steps: Option<u64>,
+    pub elevation: Option<f64>,
+    pub calories: Option<f64>,
+    pub distance: Option<f64>,
+    pub stroke: Option<u64>,
+    pub pool_lap: Option<u64>,
+    /// Seconds covered by the sample.
+    pub duration: Option<u64>,
+    pub heart_rate: Option<u64>,
+    pub spo2_auto: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn test_deserialize_intraday_activity() -> anyhow::Result<()> {
+        let body: IntradayActivityBody = serde_json::from_value(json!({
+            "series": {
+                "1643969700": {"deviceid": "cc50f3", "model": "ScanWatch", "model_id": 93, "heart_rate": 62, "duration": 60},
+                "1643969640": {"deviceid": "cc50f3", "model": "ScanWatch", "model_id": 93, "steps": 41, "calories": 1.52, "duration": 60}
+            }
+        }))?;
+
+        let samples = body.series.into_iter().collect::<Vec<_>>();
+        assert_eq!(samples[0].0, 1643969640);
+        assert_eq!(samples[0].1.steps, Some(41));
+        assert_eq!(samples[1].1.heart_rate, Some(62));
+
+        let body: IntradayActivityBody = serde_json::from_value(json!({"series": []}))?;
+        assert!(body.series.is_empty());
+        Ok(())
+    }
+}
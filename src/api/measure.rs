@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+
+use crate::de::{code_enum, number_or_string, option_number_or_string};
+use crate::params::comma_separated;
+use crate::response::WithingsResponse;
+
+pub(crate) const MEASURE_PATH: &str = "/measure";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct GetMeasRequest {
+    pub action: String, // TODO: enum
+    pub meastype: Option<MeasureType>,
+    #[serde(serialize_with = "comma_separated")]
+    pub meastypes: Option<Vec<MeasureType>>,
+    pub category: Option<Category>,
+    pub startdate: Option<u64>,
+    pub enddate: Option<u64>,
+    pub offset: Option<u64>,
+    pub lastupdate: Option<u64>,
+}
+
+code_enum! {
+    /// Measure types of `getmeas`, sent as their numeric code.
+    pub enum MeasureType {
+        Weight = 1,
+        Height = 4,
+        FatFreeMass = 5,
+        FatRatio = 6,
+        FatMassWeight = 8,
+        DiastolicBloodPressure = 9,
+        SystolicBloodPressure = 10,
+        HeartPulse = 11,
+        Temperature = 12,
+        Spo2 = 54,
+        BodyTemperature = 71,
+        SkinTemperature = 73,
+        MuscleMass = 76,
+        Hydration = 77,
+        BoneMass = 88,
+        PulseWaveVelocity = 91,
+        Vo2Max = 123,
+        AtrialFibrillation = 130,
+        QrsInterval = 135,
+        PrInterval = 136,
+        QtInterval = 137,
+        CorrectedQtInterval = 138,
+        AtrialFibrillationPpg = 139,
+        VascularAge = 155,
+        NerveHealthScore = 167,
+        ExtracellularWater = 168,
+        IntracellularWater = 169,
+        VisceralFat = 170,
+        FatFreeMassSegments = 173,
+        FatMassSegments = 174,
+        MuscleMassSegments = 175,
+        ElectrodermalActivity = 196,
+        BasalMetabolicRate = 226,
+    }
+}
+
+impl MeasureType {
+    /// snake_case name, `None` for `Unknown`.
+    pub fn name(&self) -> Option<&'static str> {
+        use MeasureType::*;
+        Some(match self {
+            Weight => "weight",
+            Height => "height",
+            FatFreeMass => "fat_free_mass",
+            FatRatio => "fat_ratio",
+            FatMassWeight => "fat_mass_weight",
+            DiastolicBloodPressure => "diastolic_blood_pressure",
+            SystolicBloodPressure => "systolic_blood_pressure",
+            HeartPulse => "heart_pulse",
+            Temperature => "temperature",
+            Spo2 => "spo2",
+            BodyTemperature => "body_temperature",
+            SkinTemperature => "skin_temperature",
+            MuscleMass => "muscle_mass",
+            Hydration => "hydration",
+            BoneMass => "bone_mass",
+            PulseWaveVelocity => "pulse_wave_velocity",
+            Vo2Max => "vo2_max",
+            AtrialFibrillation => "atrial_fibrillation",
+            QrsInterval => "qrs_interval",
+            PrInterval => "pr_interval",
+            QtInterval => "qt_interval",
+            CorrectedQtInterval => "corrected_qt_interval",
+            AtrialFibrillationPpg => "atrial_fibrillation_ppg",
+            VascularAge => "vascular_age",
+            NerveHealthScore => "nerve_health_score",
+            ExtracellularWater => "extracellular_water",
+            IntracellularWater => "intracellular_water",
+            VisceralFat => "visceral_fat",
+            FatFreeMassSegments => "fat_free_mass_segments",
+            FatMassSegments => "fat_mass_segments",
+            MuscleMassSegments => "muscle_mass_segments",
+            ElectrodermalActivity => "electrodermal_activity",
+            BasalMetabolicRate => "basal_metabolic_rate",
+            Unknown(_) => return None,
+        })
+    }
+
+    /// UCUM unit of `Measure::real_value`, `None` for unitless scores and
+    /// `Unknown`.
+    pub fn unit(&self) -> Option<&'static str> {
+        use MeasureType::*;
+        Some(match self {
+            Weight | FatFreeMass | FatMassWeight | MuscleMass | Hydration | BoneMass
+            | ExtracellularWater | IntracellularWater | FatFreeMassSegments | FatMassSegments
+            | MuscleMassSegments => "kg",
+            Height => "m",
+            FatRatio | Spo2 => "%",
+            DiastolicBloodPressure | SystolicBloodPressure => "mm[Hg]",
+            HeartPulse => "/min",
+            Temperature | BodyTemperature | SkinTemperature => "Cel",
+            PulseWaveVelocity => "m/s",
+            Vo2Max => "mL/min/kg",
+            QrsInterval | PrInterval | QtInterval | CorrectedQtInterval => "ms",
+            VascularAge => "a",
+            BasalMetabolicRate => "kcal/d",
+            AtrialFibrillation
+            | AtrialFibrillationPpg
+            | NerveHealthScore
+            | VisceralFat
+            | ElectrodermalActivity
+            | Unknown(_) => return None,
+        })
+    }
+}
+
+code_enum! {
+    pub enum Category {
+        Real = 1,
+        UserObjective = 2,
+    }
+}
+
+impl Category {
+    /// snake_case name, `None` for `Unknown`.
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Category::Real => Some("real"),
+            Category::UserObjective => Some("user_objective"),
+            Category::Unknown(_) => None,
+        }
+    }
+}
+
+pub type GetMeasResponse = WithingsResponse<MeasureBody>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MeasureBody {
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub updatetime: u64,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    pub measuregrps: Vec<MeasureGroup>,
+    #[serde(default)]
+    pub more: u64,
+    #[serde(default)]
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MeasureGroup {
+    #[serde(deserialize_with = "number_or_string")]
+    pub grpid: u64,
+    pub attrib: u64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub date: u64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub created: u64,
+    /// Last edit of the group.
+    #[serde(default, deserialize_with = "option_number_or_string")]
+    pub modified: Option<u64>,
+    pub category: Category,
+    #[serde(default)]
+    pub deviceid: Option<String>,
+    #[serde(default)]
+    pub hash_deviceid: Option<String>,
+    pub measures: Vec<Measure>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Measure {
+    pub value: i64,
+    #[serde(rename = "type")]
+    pub meastype: MeasureType,
+    pub unit: i64,
+    #[serde(default)]
+    pub algo: Option<u64>,
+    #[serde(default)]
+    pub fm: Option<u64>,
+    #[serde(default)]
+    pub apppfmid: Option<u64>,
+    #[serde(default)]
+    pub appliver: Option<u64>,
+}
+
+impl Measure {
+    /// `value * 10^unit`, i.e. the measure in its real unit.
+    pub fn real_value(&self) -> f64 {
+        // Dividing keeps e.g. 80125e-3 at 80.125 rather than 80.12500000000001.
+        if self.unit < 0 {
+            self.value as f64 / 10f64.powi(-self.unit as i32)
+        } else {
+            self.value as f64 * 10f64.powi(self.unit as i32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_get_meas_request() -> anyhow::Result<()> {
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            meastype: Some(MeasureType::Weight),
+            meastypes: Some(vec![
+                MeasureType::Weight,
+                MeasureType::FatRatio,
+                MeasureType::SystolicBloodPressure,
+            ]),
+            category: Some(Category::Real),
+            startdate: Some(1644138861),
+            enddate: None,
+            offset: None,
+            lastupdate: None,
+        };
+        assert_eq!(
+            serde_urlencoded::to_string(&req)?,
+            "action=getmeas&meastype=1&meastypes=1%2C6%2C10&category=1&startdate=1644138861"
+        );
+
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            ..Default::default()
+        };
+        assert_eq!(serde_urlencoded::to_string(&req)?, "action=getmeas");
+        Ok(())
+    }
+
+    #[test]
+    fn test_real_value() {
+        let measure = Measure {
+            value: 80125,
+            meastype: MeasureType::Weight,
+            unit: -3,
+            algo: None,
+            fm: None,
+            apppfmid: None,
+            appliver: None,
+        };
+        assert_eq!(measure.real_value(), 80.125);
+        assert_eq!(measure.real_value().to_string(), "80.125");
+    }
+
+    #[test]
+    fn test_deserialize_unknown_codes() -> anyhow::Result<()> {
+        let grp: MeasureGroup = serde_json::from_value(json!({
+            "grpid": "123456789",
+            "attrib": 0,
+            "date": 1643969671,
+            "created": "1643969717",
+            "modified": 1643979717,
+            "category": 3,
+            "measures": [
+                {"value": 80000, "type": 1, "unit": -3},
+                {"value": 42, "type": 999, "unit": 0}
+            ]
+        }))?;
+        assert_eq!(grp.grpid, 123456789);
+        assert_eq!(grp.created, 1643969717);
+        assert_eq!(grp.modified, Some(1643979717));
+        assert_eq!(grp.category, Category::Unknown(3));
+        assert_eq!(grp.measures[0].meastype, MeasureType::Weight);
+        assert_eq!(grp.measures[1].meastype, MeasureType::Unknown(999));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_updatetime() -> anyhow::Result<()> {
+        for updatetime in [json!("1644138861"), json!(1644138861)] {
+            let body: MeasureBody = serde_json::from_value(json!({
+                "updatetime": updatetime,
+                "timezone": "Asia/Tokyo",
+                "measuregrps": []
+            }))?;
+            assert_eq!(body.updatetime, 1644138861);
+        }
+        Ok(())
+    }
+}
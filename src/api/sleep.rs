@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::de::{bool_or_number, code_enum, map_or_empty_array, number_or_string};
+use crate::params::{comma_separated, YmdDate};
+use crate::response::WithingsResponse;
+
+pub(crate) const SLEEP_V2_PATH: &str = "/v2/sleep";
+
+/// `getsummary`: one summary per night. Either a date range or `lastupdate`
+/// must be set.
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct GetSleepSummaryRequest {
+    pub startdateymd: Option<YmdDate>,
+    pub enddateymd: Option<YmdDate>,
+    pub lastupdate: Option<u64>,
+    pub offset: Option<u64>,
+    #[serde(serialize_with = "comma_separated")]
+    pub data_fields: Option<Vec<String>>,
+}
+
+pub type GetSleepSummaryResponse = WithingsResponse<SleepSummaryBody>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SleepSummaryBody {
+    pub series: Vec<SleepSummary>,
+    #[serde(default, deserialize_with = "bool_or_number")]
+    pub more: bool,
+    #[serde(default)]
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SleepSummary {
+    #[serde(deserialize_with = "number_or_string")]
+    pub id: u64,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub model: Option<u64>,
+    #[serde(default)]
+    pub model_id: Option<u64>,
+    #[serde(deserialize_with = "number_or_string")]
+    pub startdate: u64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub enddate: u64,
+    pub date: YmdDate,
+    #[serde(deserialize_with = "number_or_string")]
+    pub created: u64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub modified: u64,
+    #[serde(default)]
+    pub data: SleepSummaryData,
+}
+
+/// Fields present depend on the device and on `data_fields`.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct SleepSummaryData {
+    pub breathing_disturbances_intensity: Option<u64>,
+    pub deepsleepduration: Option<u64>,
+    pub durationtosleep: Option<u64>,
+    pub durationtowakeup: Option<u64>,
+    pub hr_average: Option<u64>,
+    pub hr_max: Option<u64>,
+    pub hr_min: Option<u64>,
+    pub lightsleepduration: Option<u64>,
+    pub remsleepduration: Option<u64>,
+    pub rr_average: Option<u64>,
+    pub rr_max: Option<u64>,
+    pub rr_min: Option<u64>,
+    pub sleep_score: Option<u64>,
+    pub snoring: Option<u64>,
+    pub snoringepisodecount: Option<u64>,
+    pub wakeupcount: Option<u64>,
+    pub wakeupduration: Option<u64>,
+    pub total_sleep_time: Option<u64>,
+    pub total_timeinbed: Option<u64>,
+    pub sleep_efficiency: Option<f64>,
+    pub sleep_latency: Option<u64>,
+    pub wakeup_latency: Option<u64>,
+    pub waso: Option<u64>,
+    pub nb_rem_episodes: Option<u64>,
+    pub out_of_bed_count: Option<u64>,
+    pub apnea_hypopnea_index: Option<f64>,
+}
+
+/// `get`: sleep states and vital signs sampled during the night, for at
+/// most 24 hours per call.
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct GetSleepRequest {
+    pub startdate: Option<u64>,
+    pub enddate: Option<u64>,
+    #[serde(serialize_with = "comma_separated")]
+    pub data_fields: Option<Vec<String>>,
+}
+
+pub type GetSleepResponse = WithingsResponse<SleepBody>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SleepBody {
+    pub series: Vec<SleepSegment>,
+}
+
+code_enum! {
+    pub enum SleepState {
+        Awake = 0,
+        LightSleep = 1,
+        DeepSleep = 2,
+        Rem = 3,
+        Manual = 4,
+        Unspecified = 5,
+    }
+}
+
+impl SleepState {
+    /// snake_case name, `None` for `Unknown`.
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            SleepState::Awake => "awake",
+            SleepState::LightSleep => "light_sleep",
+            SleepState::DeepSleep => "deep_sleep",
+            SleepState::Rem => "rem",
+            SleepState::Manual => "manual",
+            SleepState::Unspecified => "unspecified",
+            SleepState::Unknown(_) => return None,
+        })
+    }
+}
+
+/// A period in one sleep state, with the samples taken during it keyed by
+/// epoch timestamp.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SleepSegment {
+    #[serde(deserialize_with = "number_or_string")]
+    pub startdate: u64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub enddate: u64,
+    pub state: SleepState,
+    #[serde(default)]
+    pub model_id: Option<u64>,
+    #[serde(default)]
+    pub hash_deviceid: Option<String>,
+    /// Heart rate, bpm.
+    #[serde(default, deserialize_with = "map_or_empty_array")]
+    pub hr: BTreeMap<u64, f64>,
+    /// Respiration rate, breaths per minute.
+    #[serde(default, deserialize_with = "map_or_empty_array")]
+    pub rr: BTreeMap<u64, f64>,
+    /// Seconds of snoring.
+    #[serde(default, deserialize_with = "map_or_empty_array")]
+    pub snoring: BTreeMap<u64, f64>,
+    /// Heart rate variability, ms.
+    #[serde(default, deserialize_with = "map_or_empty_array")]
+    pub sdnn_1: BTreeMap<u64, f64>,
+    #[serde(default, deserialize_with = "map_or_empty_array")]
+    pub rmssd: BTreeMap<u64, f64>,
+    #[serde(default, deserialize_with = "map_or_empty_array")]
+    pub mvt_score: BTreeMap<u64, f64>,
+}
+
+impl SleepSegment {
+    /// Samples of all signals as `(signal, timestamp, value)`, e.g.
+    /// `("hr", 1643899860, 58.0)`.
+    pub fn samples(&self) -> impl Iterator<Item = (&'static str, u64, f64)> + '_ {
+        [
+            ("hr", &self.hr),
+            ("rr", &self.rr),
+            ("snoring", &self.snoring),
+            ("sdnn_1", &self.sdnn_1),
+            ("rmssd", &self.rmssd),
+            ("mvt_score", &self.mvt_score),
+        ]
+        .into_iter()
+        .flat_map(|(signal, series)| series.iter().map(move |(t, v)| (signal, *t, *v)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_get_sleep_summary_request() -> anyhow::Result<()> {
+        let req = GetSleepSummaryRequest {
+            lastupdate: Some(1644138861),
+            data_fields: Some(vec!["sleep_score".into(), "hr_average".into()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_urlencoded::to_string(&req)?,
+            "lastupdate=1644138861&data_fields=sleep_score%2Chr_average"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_sleep_summary() -> anyhow::Result<()> {
+        let body: SleepSummaryBody = serde_json::from_value(json!({
+            "series": [{
+                "id": 2081804,
+                "timezone": "Asia/Tokyo",
+                "model": 32,
+                "model_id": 63,
+                "startdate": 1643899800,
+                "enddate": 1643926500,
+                "date": "2022-02-04",
+                "created": 1643927120,
+                "modified": "1643927130",
+                "data": {
+                    "deepsleepduration": 5400,
+                    "sleep_score": 81,
+                    "sleep_efficiency": 0.92
+                }
+            }],
+            "more": true,
+            "offset": 1
+        }))?;
+
+        assert!(body.more);
+        assert_eq!(body.offset, 1);
+        let summary = &body.series[0];
+        assert_eq!(summary.date, YmdDate::new(2022, 2, 4));
+        assert_eq!(summary.modified, 1643927130);
+        assert_eq!(summary.data.sleep_score, Some(81));
+        assert_eq!(summary.data.hr_average, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_sleep_series() -> anyhow::Result<()> {
+        let body: SleepBody = serde_json::from_value(json!({
+            "series": [
+                {
+                    "startdate": 1643899800,
+                    "enddate": 1643900400,
+                    "state": 1,
+                    "model_id": 63,
+                    "hr": {"1643899800": 58, "1643900100": 56},
+                    "rr": {"1643899800": 14},
+                    "snoring": []
+                },
+                {"startdate": "1643900400", "enddate": 1643901000, "state": 7}
+            ]
+        }))?;
+
+        let segment = &body.series[0];
+        assert_eq!(segment.state, SleepState::LightSleep);
+        assert_eq!(
+            segment.samples().collect::<Vec<_>>(),
+            vec![
+                ("hr", 1643899800, 58.0),
+                ("hr", 1643900100, 56.0),
+                ("rr", 1643899800, 14.0)
+            ]
+        );
+        assert_eq!(body.series[1].state, SleepState::Unknown(7));
+        assert_eq!(body.series[1].samples().count(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use serde::Deserialize;
+
+use crate::de::option_number_or_string;
+use crate::response::WithingsResponse;
+
+pub(crate) const USER_V2_PATH: &str = "/v2/user";
+
+pub type GetDevicesResponse = WithingsResponse<DevicesBody>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DevicesBody {
+    pub devices: Vec<Device>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Device {
+    pub deviceid: String,
+    #[serde(default)]
+    pub hash_deviceid: Option<String>,
+    /// e.g. `Scale`, `Blood Pressure Monitor`.
+    #[serde(rename = "type")]
+    pub device_type: String,
+    pub model: String,
+    #[serde(default, deserialize_with = "option_number_or_string")]
+    pub model_id: Option<u64>,
+    /// `low`, `medium` or `high`.
+    #[serde(default)]
+    pub battery: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default, deserialize_with = "option_number_or_string")]
+    pub first_session_date: Option<u64>,
+    #[serde(default, deserialize_with = "option_number_or_string")]
+    pub last_session_date: Option<u64>,
+}
+
+impl Device {
+    /// Last sync of the device, 0 if it never synced.
+    pub fn last_seen(&self) -> u64 {
+        self.last_session_date.unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_deserialize_devices() -> anyhow::Result<()> {
+        let body: DevicesBody = serde_json::from_value(json!({
+            "devices": [{
+                "type": "Scale",
+                "model": "Body Cardio",
+                "model_id": 6,
+                "battery": "high",
+                "deviceid": "cc50f32653df14137da15aaaaa7b2e07",
+                "hash_deviceid": "f32bbbb318f14137da157b2e07",
+                "timezone": "Asia/Tokyo",
+                "last_session_date": "1643969717"
+            }]
+        }))?;
+
+        let device = &body.devices[0];
+        assert_eq!(device.device_type, "Scale");
+        assert_eq!(device.model_id, Some(6));
+        assert_eq!(device.last_seen(), 1643969717);
+        assert_eq!(device.first_session_date, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+use crate::de::{bool_or_number, number_or_string, option_number_or_string};
+use crate::params::{comma_separated, YmdDate};
+use crate::response::WithingsResponse;
+
+/// `getworkouts`, sent to the same `/v2/measure` service as `getactivity`.
+/// Either a date range or `lastupdate` must be set.
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+pub struct GetWorkoutsRequest {
+    pub startdateymd: Option<YmdDate>,
+    pub enddateymd: Option<YmdDate>,
+    pub lastupdate: Option<u64>,
+    pub offset: Option<u64>,
+    #[serde(serialize_with = "comma_separated")]
+    pub data_fields: Option<Vec<String>>,
+}
+
+pub type GetWorkoutsResponse = WithingsResponse<WorkoutsBody>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WorkoutsBody {
+    pub series: Vec<Workout>,
+    #[serde(default, deserialize_with = "bool_or_number")]
+    pub more: bool,
+    #[serde(default)]
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Workout {
+    #[serde(deserialize_with = "number_or_string")]
+    pub id: u64,
+    pub category: u64,
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub model: Option<u64>,
+    #[serde(default)]
+    pub attrib: Option<u64>,
+    #[serde(deserialize_with = "number_or_string")]
+    pub startdate: u64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub enddate: u64,
+    pub date: YmdDate,
+    #[serde(default)]
+    pub deviceid: Option<String>,
+    #[serde(default, deserialize_with = "option_number_or_string")]
+    pub modified: Option<u64>,
+    #[serde(default)]
+    pub data: WorkoutData,
+}
+
+/// Fields present depend on the workout category and on `data_fields`.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct WorkoutData {
+    pub calories: Option<f64>,
+    pub intensity: Option<u64>,
+    pub manual_distance: Option<f64>,
+    pub manual_calories: Option<f64>,
+    pub hr_average: Option<u64>,
+    pub hr_min: Option<u64>,
+    pub hr_max: Option<u64>,
+    pub hr_zone_0: Option<u64>,
+    pub hr_zone_1: Option<u64>,
+    pub hr_zone_2: Option<u64>,
+    pub hr_zone_3: Option<u64>,
+    pub pause_duration: Option<u64>,
+    pub algo_pause_duration: Option<u64>,
+    pub spo2_average: Option<u64>,
+    pub steps: Option<u64>,
+    pub distance: Option<f64>,
+    pub elevation: Option<f64>,
+    pub pool_laps: Option<u64>,
+    pub strokes: Option<u64>,
+    pub pool_length: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_get_workouts_request() -> anyhow::Result<()> {
+        let req = GetWorkoutsRequest {
+            startdateymd: Some(YmdDate::new(2021, 1, 1)),
+            enddateymd: Some(YmdDate::new(2021, 12, 31)),
+            offset: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_urlencoded::to_string(&req)?,
+            "startdateymd=2021-01-01&enddateymd=2021-12-31&offset=10"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_workouts() -> anyhow::Result<()> {
+        let body: WorkoutsBody = serde_json::from_value(json!({
+            "series": [{
+                "id": 1928374,
+                "category": 1,
+                "timezone": "Asia/Tokyo",
+                "model": 1059,
+                "attrib": 0,
+                "startdate": 1643950800,
+                "enddate": 1643953200,
+                "date": "2022-02-04",
+                "deviceid": "cc50f32653df14137da15aaaaa7b2e07",
+                "data": {"calories": 201.5, "steps": 3120, "hr_average": 131}
+            }],
+            "more": 0,
+            "offset": 0
+        }))?;
+
+        assert!(!body.more);
+        let workout = &body.series[0];
+        assert_eq!(workout.id, 1928374);
+        assert_eq!(workout.modified, None);
+        assert_eq!(workout.data.steps, Some(3120));
+        Ok(())
+    }
+}
@@ -0,0 +1,482 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayRef, Float64Array, StringDictionaryBuilder, TimestampSecondArray, UInt32Array,
+    UInt64Array,
+};
+use arrow::compute::take_record_batch;
+use arrow::datatypes::{DataType, Field, Int32Type, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use chrono_tz::Tz;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::api::intraday::{IntradayActivityBody, IntradaySample};
+use crate::api::measure::MeasureGroup;
+use crate::api::sleep::{SleepBody, SleepState};
+use crate::time::{from_timestamp, parse_timezone};
+use crate::Result;
+
+/// One row per measure: `date`, `grpid`, `category`, `type`, `unit` and the
+/// real `value`. Categorical columns are dictionary encoded.
+pub fn measures_batch(groups: &[MeasureGroup], tz: Tz) -> Result<RecordBatch> {
+    let measures = groups
+        .iter()
+        .flat_map(|grp| grp.measures.iter().map(move |m| (grp, m)));
+
+    let mut date = vec![];
+    let mut grpid = vec![];
+    let mut category = dictionary();
+    let mut meastype = dictionary();
+    let mut unit = dictionary();
+    let mut value = vec![];
+    for (grp, m) in measures {
+        date.push(grp.date as i64);
+        grpid.push(grp.grpid);
+        category.append_value(
+            grp.category
+                .name()
+                .map(str::to_string)
+                .unwrap_or_else(|| grp.category.code().to_string()),
+        );
+        meastype.append_value(
+            m.meastype
+                .name()
+                .map(str::to_string)
+                .unwrap_or_else(|| m.meastype.code().to_string()),
+        );
+        unit.append_option(m.meastype.unit());
+        value.push(m.real_value());
+    }
+
+    batch(vec![
+        (timestamp_field("date", tz, false), timestamps(date, tz)),
+        (
+            Field::new("grpid", DataType::UInt64, false),
+            Arc::new(UInt64Array::from(grpid)),
+        ),
+        (
+            dictionary_field("category", false),
+            Arc::new(category.finish()),
+        ),
+        (dictionary_field("type", false), Arc::new(meastype.finish())),
+        (dictionary_field("unit", true), Arc::new(unit.finish())),
+        (
+            Field::new("value", DataType::Float64, false),
+            Arc::new(Float64Array::from(value)),
+        ),
+    ])
+}
+
+/// One row per `getintradayactivity` sample.
+pub fn intraday_batch(body: &IntradayActivityBody, tz: Tz) -> Result<RecordBatch> {
+    let samples = &body.series;
+    let mut deviceid = dictionary();
+    let mut model = dictionary();
+    for sample in samples.values() {
+        deviceid.append_option(sample.deviceid.as_deref());
+        model.append_option(sample.model.as_deref());
+    }
+    let u64s = |f: fn(&IntradaySample) -> Option<u64>| -> ArrayRef {
+        Arc::new(samples.values().map(f).collect::<UInt64Array>())
+    };
+    let f64s = |f: fn(&IntradaySample) -> Option<f64>| -> ArrayRef {
+        Arc::new(samples.values().map(f).collect::<Float64Array>())
+    };
+
+    batch(vec![
+        (
+            timestamp_field("timestamp", tz, false),
+            timestamps(samples.keys().map(|t| *t as i64).collect(), tz),
+        ),
+        (
+            dictionary_field("deviceid", true),
+            Arc::new(deviceid.finish()),
+        ),
+        (dictionary_field("model", true), Arc::new(model.finish())),
+        (u64_field("model_id"), u64s(|s| s.model_id)),
+        (u64_field("steps"), u64s(|s| s.steps)),
+        (f64_field("elevation"), f64s(|s| s.elevation)),
+        (f64_field("calories"), f64s(|s| s.calories)),
+        (f64_field("distance"), f64s(|s| s.distance)),
+        (u64_field("stroke"), u64s(|s| s.stroke)),
+        (u64_field("pool_lap"), u64s(|s| s.pool_lap)),
+        (u64_field("duration"), u64s(|s| s.duration)),
+        (u64_field("heart_rate"), u64s(|s| s.heart_rate)),
+        (f64_field("spo2_auto"), f64s(|s| s.spo2_auto)),
+    ])
+}
+
+/// One row per sleep state period: `startdate`, `enddate`, `state` and
+/// `model_id`.
+pub fn sleep_states_batch(body: &SleepBody, tz: Tz) -> Result<RecordBatch> {
+    let mut state = dictionary();
+    for segment in &body.series {
+        state.append_value(state_name(segment.state));
+    }
+    let series = &body.series;
+
+    batch(vec![
+        (
+            timestamp_field("startdate", tz, false),
+            timestamps(series.iter().map(|s| s.startdate as i64).collect(), tz),
+        ),
+        (
+            timestamp_field("enddate", tz, false),
+            timestamps(series.iter().map(|s| s.enddate as i64).collect(), tz),
+        ),
+        (dictionary_field("state", false), Arc::new(state.finish())),
+        (
+            u64_field("model_id"),
+            Arc::new(series.iter().map(|s| s.model_id).collect::<UInt64Array>()),
+        ),
+    ])
+}
+
+/// One row per vital sign sample taken during sleep: `timestamp`, `signal`
+/// (`hr`, `rr`, ...), `value` and the sleep `state` at that time.
+pub fn sleep_samples_batch(body: &SleepBody, tz: Tz) -> Result<RecordBatch> {
+    let mut timestamp = vec![];
+    let mut signal = dictionary();
+    let mut value = vec![];
+    let mut state = dictionary();
+    for segment in &body.series {
+        for (name, t, v) in segment.samples() {
+            timestamp.push(t as i64);
+            signal.append_value(name);
+            value.push(v);
+            state.append_value(state_name(segment.state));
+        }
+    }
+
+    batch(vec![
+        (
+            timestamp_field("timestamp", tz, false),
+            timestamps(timestamp, tz),
+        ),
+        (dictionary_field("signal", false), Arc::new(signal.finish())),
+        (
+            Field::new("value", DataType::Float64, false),
+            Arc::new(Float64Array::from(value)),
+        ),
+        (dictionary_field("state", false), Arc::new(state.finish())),
+    ])
+}
+
+/// Writes batches to Parquet files partitioned by user and local day, as
+/// `root/user_id=<id>/date=<YYYY-MM-DD>/<name>.parquet`, a layout query
+/// engines read as partition columns.
+#[derive(Debug, Clone)]
+pub struct ParquetDataset {
+    root: PathBuf,
+    compression: Compression,
+}
+
+impl ParquetDataset {
+    pub fn new(root: impl Into<PathBuf>) -> ParquetDataset {
+        ParquetDataset {
+            root: root.into(),
+            compression: Compression::SNAPPY,
+        }
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> ParquetDataset {
+        self.compression = compression;
+        self
+    }
+
+    /// Split `batch` by the day of its first column, a timestamp as in the
+    /// batches built by this module, in that column's timezone. Each day's
+    /// rows go to a new part file, `<name>-<first timestamp>-<n>.parquet`,
+    /// next to those of earlier calls; writing the same rows twice stores
+    /// them twice.
+    pub fn write(&self, user_id: u64, name: &str, batch: &RecordBatch) -> Result<Vec<PathBuf>> {
+        let (times, tz) = partition_column(batch)?;
+        let mut days = BTreeMap::<_, Vec<u32>>::new();
+        for (row, secs) in times.values().iter().enumerate() {
+            let day = from_timestamp(*secs as u64, &tz)
+                .map(|at| at.date_naive())
+                .ok_or_else(|| {
+                    ArrowError::InvalidArgumentError(format!("bad timestamp {}", secs))
+                })?;
+            days.entry(day).or_default().push(row as u32);
+        }
+
+        let props = WriterProperties::builder()
+            .set_compression(self.compression)
+            .build();
+        let mut paths = vec![];
+        for (day, rows) in days {
+            let dir = self
+                .root
+                .join(format!("user_id={}", user_id))
+                .join(format!("date={}", day));
+            fs::create_dir_all(&dir)?;
+            let first = times.value(rows[0] as usize);
+            let (path, file) = create_part(&dir, name, first)?;
+            let rows = take_record_batch(batch, &UInt32Array::from(rows))?;
+            let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props.clone()))?;
+            writer.write(&rows)?;
+            writer.close()?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+/// Create the first part file of `dir` that doesn't exist yet.
+fn create_part(dir: &Path, name: &str, first: i64) -> Result<(PathBuf, File)> {
+    for n in 0.. {
+        let path = dir.join(format!("{}-{}-{}.parquet", name, first, n));
+        match File::create_new(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    unreachable!()
+}
+
+fn partition_column(batch: &RecordBatch) -> Result<(&TimestampSecondArray, Tz)> {
+    let column = batch.columns().first().and_then(|c| {
+        c.as_any()
+            .downcast_ref::<TimestampSecondArray>()
+            .filter(|c| c.null_count() == 0)
+    });
+    let Some(column) = column else {
+        return Err(ArrowError::InvalidArgumentError(
+            "first column must be a non-null second timestamp".into(),
+        )
+        .into());
+    };
+    let tz = column
+        .timezone()
+        .and_then(parse_timezone)
+        .unwrap_or(Tz::UTC);
+    Ok((column, tz))
+}
+
+fn state_name(state: SleepState) -> String {
+    state
+        .name()
+        .map(str::to_string)
+        .unwrap_or_else(|| state.code().to_string())
+}
+
+fn batch(columns: Vec<(Field, ArrayRef)>) -> Result<RecordBatch> {
+    let (fields, arrays): (Vec<_>, Vec<_>) = columns.into_iter().unzip();
+    Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+}
+
+fn dictionary() -> StringDictionaryBuilder<Int32Type> {
+    StringDictionaryBuilder::new()
+}
+
+fn timestamps(secs: Vec<i64>, tz: Tz) -> ArrayRef {
+    Arc::new(TimestampSecondArray::from(secs).with_timezone(tz.name()))
+}
+
+fn timestamp_field(name: &str, tz: Tz, nullable: bool) -> Field {
+    Field::new(
+        name,
+        DataType::Timestamp(TimeUnit::Second, Some(tz.name().into())),
+        nullable,
+    )
+}
+
+fn dictionary_field(name: &str, nullable: bool) -> Field {
+    Field::new(
+        name,
+        DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+        nullable,
+    )
+}
+
+fn u64_field(name: &str) -> Field {
+    Field::new(name, DataType::UInt64, true)
+}
+
+fn f64_field(name: &str) -> Field {
+    Field::new(name, DataType::Float64, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::measure::MeasureBody;
+    use crate::test_util::{group, measure_body, objective, weigh_in};
+    use arrow::array::{AsArray, DictionaryArray};
+    use arrow::datatypes::{Float64Type, UInt64Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn dictionary_values(batch: &RecordBatch, name: &str) -> Vec<Option<String>> {
+        let column = batch
+            .column_by_name(name)
+            .unwrap()
+            .as_any()
+            .downcast_ref::<DictionaryArray<Int32Type>>()
+            .unwrap();
+        let values = column.values().as_string::<i32>();
+        column
+            .keys()
+            .iter()
+            .map(|k| k.map(|k| values.value(k as usize).to_string()))
+            .collect()
+    }
+
+    fn measures() -> MeasureBody {
+        measure_body(
+            Some("Asia/Tokyo"),
+            vec![weigh_in(), objective(2, 1644019200)],
+        )
+    }
+
+    #[test]
+    fn test_measures_batch() -> anyhow::Result<()> {
+        let batch = measures_batch(&measures().measuregrps, chrono_tz::Asia::Tokyo)?;
+
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(
+            batch.schema().field(0).data_type(),
+            &DataType::Timestamp(TimeUnit::Second, Some("Asia/Tokyo".into()))
+        );
+        assert_eq!(
+            dictionary_values(&batch, "type"),
+            vec![
+                Some("weight".into()),
+                Some("999".into()),
+                Some("weight".into())
+            ]
+        );
+        assert_eq!(
+            dictionary_values(&batch, "unit"),
+            vec![Some("kg".into()), None, Some("kg".into())]
+        );
+        assert_eq!(
+            dictionary_values(&batch, "category")[2].as_deref(),
+            Some("user_objective")
+        );
+        let value = batch
+            .column_by_name("value")
+            .unwrap()
+            .as_primitive::<Float64Type>();
+        assert_eq!(value.values().to_vec(), vec![80.125, 42.0, 75.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_intraday_and_sleep_batches() -> anyhow::Result<()> {
+        let intraday: IntradayActivityBody = serde_json::from_value(json!({
+            "series": {
+                "1643969640": {"deviceid": "cc50f3", "steps": 41, "calories": 1.52},
+                "1643969700": {"deviceid": "cc50f3", "heart_rate": 62}
+            }
+        }))?;
+        let batch = intraday_batch(&intraday, Tz::UTC)?;
+        assert_eq!(batch.num_rows(), 2);
+        let steps = batch
+            .column_by_name("steps")
+            .unwrap()
+            .as_primitive::<UInt64Type>();
+        assert_eq!(steps.iter().collect::<Vec<_>>(), vec![Some(41), None]);
+
+        let sleep: SleepBody = serde_json::from_value(json!({
+            "series": [
+                {"startdate": 1643899800, "enddate": 1643900400, "state": 2, "hr": {"1643899800": 58}, "rr": {"1643899800": 14}},
+                {"startdate": 1643900400, "enddate": 1643901000, "state": 0}
+            ]
+        }))?;
+        let states = sleep_states_batch(&sleep, Tz::UTC)?;
+        assert_eq!(
+            dictionary_values(&states, "state"),
+            vec![Some("deep_sleep".into()), Some("awake".into())]
+        );
+        let samples = sleep_samples_batch(&sleep, Tz::UTC)?;
+        assert_eq!(
+            dictionary_values(&samples, "signal"),
+            vec![Some("hr".into()), Some("rr".into())]
+        );
+        assert_eq!(
+            dictionary_values(&samples, "state"),
+            vec![Some("deep_sleep".into()), Some("deep_sleep".into())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_partitioned() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("withings_parquet_{}", std::process::id()));
+        let dataset = ParquetDataset::new(&root);
+        let batch = measures_batch(&measures().measuregrps, chrono_tz::Asia::Tokyo)?;
+
+        let paths = dataset.write(363, "measures", &batch)?;
+        // 1644019200 is midnight UTC, already the next day in Tokyo.
+        assert_eq!(
+            paths,
+            vec![
+                root.join("user_id=363/date=2022-02-04/measures-1643969671-0.parquet"),
+                root.join("user_id=363/date=2022-02-05/measures-1644019200-0.parquet"),
+            ]
+        );
+
+        let grpids = |path: &Path| -> anyhow::Result<Vec<u64>> {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+            let mut grpids = vec![];
+            for b in reader {
+                let b = b?;
+                assert_eq!(b.schema(), batch.schema());
+                grpids.extend(
+                    b.column_by_name("grpid")
+                        .unwrap()
+                        .as_primitive::<UInt64Type>()
+                        .values()
+                        .iter(),
+                );
+            }
+            Ok(grpids)
+        };
+        assert_eq!(grpids(&paths[0])?, vec![1, 1]);
+        assert_eq!(grpids(&paths[1])?, vec![2]);
+
+        // A later sync of the same day adds a part file next to the first.
+        let later = measure_body(None, vec![group(3, 1643980000)]);
+        let later = measures_batch(&later.measuregrps, chrono_tz::Asia::Tokyo)?;
+        assert_eq!(
+            dataset.write(363, "measures", &later)?,
+            vec![root.join("user_id=363/date=2022-02-04/measures-1643980000-0.parquet")]
+        );
+        assert_eq!(
+            dataset.write(363, "measures", &batch)?[0],
+            root.join("user_id=363/date=2022-02-04/measures-1643969671-1.parquet")
+        );
+        let mut day = fs::read_dir(root.join("user_id=363/date=2022-02-04"))?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        day.sort();
+        let rows = day
+            .iter()
+            .map(|path| grpids(path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(rows, vec![vec![1, 1], vec![1, 1], vec![3]]);
+
+        let not_partitionable = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![u64_field("grpid")])),
+            vec![Arc::new(UInt64Array::from(vec![1]))],
+        )?;
+        assert!(dataset.write(363, "measures", &not_partitionable).is_err());
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
@@ -4,25 +4,80 @@ use serde::de;
 use serde::de::Deserializer;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
+use std::time::Duration;
 use url::Url;
 
-use crate::error::handle_response;
+use crate::auth::login::DEFAULT_LOGIN_TIMEOUT;
+use crate::de::number_or_string;
+use crate::error::{AuthorizationError, RequestMeta};
+use crate::rate_limit::RateLimiter;
+use crate::response::WithingsResponse;
+use crate::retry::{send_with_retry, Dispatch, RetryPolicy};
+use crate::secret::Secret;
+use crate::Result;
 
 const AUTH2_TOKEN_PATH: &str = "/v2/oauth2";
 
-pub const WITHINGS_ACCOUNT_URL: &str = "https://account.withings.com";
+pub use crate::WITHINGS_ACCOUNT_URL;
 const AUTHORIZE_PATH: &str = "/oauth2_user/authorize2";
 
 #[derive(Debug, Clone)]
 pub struct AuthCli {
     pub response_type: String,
     pub client_id: String,
-    pub consumer_secret: String,
+    pub consumer_secret: Secret,
     pub callback_uri: String,
     pub scope: Vec<Scope>,
+    /// Withings `mode` parameter of the authorize URL, e.g. `demo`.
     pub mode: Option<String>,
     pub client: reqwest::Client,
     pub base_api_url: String,
+    pub account_url: String,
+    pub retry_policy: RetryPolicy,
+    pub rate_limiter: Option<RateLimiter>,
+    /// See `ApiCli::with_strict`.
+    pub strict: bool,
+    /// Deadline of `login_with_loopback`.
+    pub login_timeout: Duration,
+}
+
+/// OAuth `state` parameter used to protect the authorization redirect against CSRF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State(String);
+
+impl State {
+    /// Generate a fresh state from 32 cryptographically random bytes. Use a
+    /// new one for every authorization request.
+    pub fn random() -> State {
+        let bytes: [u8; 32] = rand::random();
+        State(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    pub fn new(state: String) -> State {
+        State(state)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Check the `state` returned on the callback against the one sent with
+    /// the authorize URL.
+    pub fn verify(&self, returned: &str) -> std::result::Result<(), AuthorizationError> {
+        let expected = self.0.as_bytes();
+        let returned = returned.as_bytes();
+        let diff = expected
+            .iter()
+            .zip(returned)
+            .fold(expected.len() ^ returned.len(), |acc, (a, b)| {
+                acc | (a ^ b) as usize
+            });
+        if diff == 0 {
+            Ok(())
+        } else {
+            Err(AuthorizationError::StateMismatch)
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -30,37 +85,36 @@ pub struct AccessTokenRequest {
     pub action: String,
     pub grant_type: String,
     pub client_id: String,
-    pub client_secret: String,
+    pub client_secret: Secret,
     pub redirect_uri: String,
-    pub code: String,
+    pub code: Secret,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
-pub struct AccessTokenResponse {
-    pub status: u64,
-    pub body: AccessToken,
-}
+pub type AccessTokenResponse = WithingsResponse<AccessToken>;
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct AccessToken {
-    #[serde(rename = "userid")]
+    #[serde(rename = "userid", deserialize_with = "number_or_string")]
     pub user_id: u64,
-    pub access_token: String,
-    pub refresh_token: String,
+    pub access_token: Secret,
+    pub refresh_token: Secret,
+    #[serde(deserialize_with = "number_or_string")]
     pub expires_in: u64,
     #[serde(deserialize_with = "deserialize_vec_scope")]
     pub scope: Vec<Scope>,
     pub token_type: String,
 }
 
-fn deserialize_vec_scope<'de, D>(deserializer: D) -> Result<Vec<Scope>, D::Error>
+fn deserialize_vec_scope<'de, D>(deserializer: D) -> std::result::Result<Vec<Scope>, D::Error>
 where
     D: Deserializer<'de>,
 {
     String::deserialize(deserializer)?
         .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
         .map(Scope::from_str)
-        .collect::<Result<Vec<_>, _>>()
+        .collect::<std::result::Result<Vec<_>, _>>()
         .map_err(de::Error::custom)
 }
 
@@ -69,22 +123,19 @@ pub struct RefreshTokenRequest {
     pub action: String,
     pub grant_type: String,
     pub client_id: String,
-    pub client_secret: String,
-    pub refresh_token: String,
+    pub client_secret: Secret,
+    pub refresh_token: Secret,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
-pub struct RefreshTokenResponse {
-    pub status: u64,
-    pub body: RefreshToken,
-}
+pub type RefreshTokenResponse = WithingsResponse<RefreshToken>;
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct RefreshToken {
-    #[serde(rename = "userid")]
+    #[serde(rename = "userid", deserialize_with = "number_or_string")]
     pub user_id: u64,
-    pub access_token: String,
-    pub refresh_token: String,
+    pub access_token: Secret,
+    pub refresh_token: Secret,
+    #[serde(deserialize_with = "number_or_string")]
     pub expires_in: u64,
     #[serde(deserialize_with = "deserialize_vec_scope")]
     pub scope: Vec<Scope>,
@@ -95,9 +146,9 @@ pub struct RefreshToken {
     Debug,
     Clone,
     PartialEq,
-    Deserialize,
+    Eq,
+    Hash,
     strum_macros::EnumString,
-    strum_macros::Display,
     strum_macros::IntoStaticStr,
     strum_macros::EnumIter,
 )]
@@ -110,6 +161,37 @@ pub enum Scope {
     UserActivity,
     #[strum(serialize = "user.sleepevents")]
     UserSleepEvents,
+    /// Scope granted by Withings that this crate doesn't know yet. Iterated
+    /// last as `Unknown("")`; its static str is `"Unknown"`, use `as_str` for
+    /// the scope itself.
+    #[strum(default)]
+    Unknown(String),
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Scope::UserInfo => "user.info",
+            Scope::UserMetrics => "user.metrics",
+            Scope::UserActivity => "user.activity",
+            Scope::UserSleepEvents => "user.sleepevents",
+            Scope::Unknown(scope) => scope,
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Scope {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Scope, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
 }
 
 impl AuthCli {
@@ -124,16 +206,58 @@ impl AuthCli {
         AuthCli {
             base_api_url,
             client_id,
-            consumer_secret,
+            consumer_secret: consumer_secret.into(),
             callback_uri,
             scope,
             mode,
             response_type: "code".into(),
             client: reqwest::Client::new(),
+            account_url: WITHINGS_ACCOUNT_URL.into(),
+            retry_policy: RetryPolicy::none(),
+            rate_limiter: None,
+            strict: false,
+            login_timeout: DEFAULT_LOGIN_TIMEOUT,
+        }
+    }
+
+    /// Point the authorization endpoint at another account server, e.g. a
+    /// regional endpoint or a local stand-in for tests.
+    pub fn with_account_url(mut self, account_url: String) -> AuthCli {
+        self.account_url = account_url;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> AuthCli {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> AuthCli {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> AuthCli {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_login_timeout(mut self, login_timeout: Duration) -> AuthCli {
+        self.login_timeout = login_timeout;
+        self
+    }
+
+    fn dispatch(&self) -> Dispatch<'_> {
+        Dispatch {
+            retry_policy: &self.retry_policy,
+            rate_limiter: self.rate_limiter.as_ref(),
+            user_id: None,
+            idempotent: false,
+            strict: self.strict,
         }
     }
 
-    pub fn get_authorize_url(&self) -> anyhow::Result<String> {
+    pub fn get_authorize_url(&self, state: &State) -> Result<String> {
         let mut q = vec![
             ("response_type", &self.response_type),
             ("client_id", &self.client_id),
@@ -143,7 +267,7 @@ impl AuthCli {
         let s = &self
             .scope
             .iter()
-            .map(|x| x.into())
+            .map(Scope::as_str)
             .collect::<Vec<&str>>()
             .join(",");
 
@@ -151,18 +275,67 @@ impl AuthCli {
             q.push(("scope", s));
         }
 
-        let default_mode: String = "dev".into();
-        match &self.mode {
-            Some(m) => q.push(("state", m)),
-            None => q.push(("state", &default_mode)),
+        if let Some(m) = &self.mode {
+            q.push(("mode", m));
         }
 
-        let url =
-            Url::parse_with_params(&format!("{}{}", WITHINGS_ACCOUNT_URL, AUTHORIZE_PATH), q)?;
+        q.push(("state", &state.0));
+
+        let url = Url::parse_with_params(
+            &format!(
+                "{}{}",
+                self.account_url.trim_end_matches('/'),
+                AUTHORIZE_PATH
+            ),
+            q,
+        )?;
         Ok(url.into())
     }
 
-    pub async fn get_access_token(&self, code: &str) -> anyhow::Result<AccessTokenResponse> {
+    /// Extract the authorization code from the redirect to `callback_uri`.
+    /// Accepts the full redirect URL or only its query string.
+    pub fn parse_callback(&self, redirect: &str, state: &State) -> Result<Secret> {
+        let query = match redirect.split_once('?') {
+            Some((_, q)) => q,
+            None => redirect,
+        };
+        let query = query.split('#').next().unwrap_or_default();
+
+        let mut code = None;
+        let mut returned_state = None;
+        let mut error = None;
+        let mut error_description = None;
+        for (k, v) in url::form_urlencoded::parse(query.as_bytes()) {
+            match k.as_ref() {
+                "code" => code = Some(Secret::new(v.into_owned())),
+                "state" => returned_state = Some(v.into_owned()),
+                "error" => error = Some(v.into_owned()),
+                "error_description" => error_description = Some(v.into_owned()),
+                _ => {}
+            }
+        }
+
+        state.verify(returned_state.as_deref().unwrap_or_default())?;
+
+        if let Some(error) = error {
+            return Err(AuthorizationError::AuthorizationDenied {
+                error,
+                description: error_description,
+            }
+            .into());
+        }
+
+        code.filter(|c| !c.expose().is_empty())
+            .ok_or_else(|| AuthorizationError::MissingCode.into())
+    }
+
+    /// `parse_callback` followed by `get_access_token`.
+    pub async fn exchange_callback(&self, redirect: &str, state: &State) -> Result<AccessToken> {
+        let code = self.parse_callback(redirect, state)?;
+        self.get_access_token(code.expose()).await
+    }
+
+    pub async fn get_access_token(&self, code: &str) -> Result<AccessToken> {
         let req = AccessTokenRequest {
             action: "requesttoken".into(),
             grant_type: "authorization_code".into(),
@@ -172,20 +345,14 @@ impl AuthCli {
             code: code.into(),
         };
 
-        let res = self
-            .client
-            .post(format!("{}{}", &self.base_api_url, AUTH2_TOKEN_PATH))
-            .form(&req)
-            .send()
-            .await?;
+        let url = format!("{}{}", &self.base_api_url, AUTH2_TOKEN_PATH);
+        let meta = RequestMeta::new(&url, &req.action);
+        let req = self.client.post(url).form(&req);
 
-        Ok(handle_response(req, res).await?)
+        send_with_retry(self.dispatch(), meta, req).await
     }
 
-    pub async fn get_refresh_token(
-        &self,
-        refresh_token: &str,
-    ) -> anyhow::Result<RefreshTokenResponse> {
+    pub async fn get_refresh_token(&self, refresh_token: &str) -> Result<RefreshToken> {
         let req = RefreshTokenRequest {
             action: "requesttoken".into(),
             grant_type: "refresh_token".into(),
@@ -194,21 +361,18 @@ impl AuthCli {
             refresh_token: refresh_token.into(),
         };
 
-        let res = self
-            .client
-            .post(format!("{}{}", &self.base_api_url, AUTH2_TOKEN_PATH))
-            .form(&req)
-            .send()
-            .await?;
+        let url = format!("{}{}", &self.base_api_url, AUTH2_TOKEN_PATH);
+        let meta = RequestMeta::new(&url, &req.action);
+        let req = self.client.post(url).form(&req);
 
-        Ok(handle_response(req, res).await?)
+        send_with_retry(self.dispatch(), meta, req).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{error::WithingsApiError, WITHINGS_API_URL};
+    use crate::{error::WithingsApiError, Error, WITHINGS_API_URL};
     use assert_matches::assert_matches;
     use reqwest::StatusCode;
     use rstest::rstest;
@@ -216,13 +380,97 @@ mod tests {
 
     #[test]
     fn test_scope() -> anyhow::Result<()> {
-        let scope = vec![Scope::UserInfo, Scope::UserMetrics];
+        let scope = [Scope::UserInfo, Scope::UserMetrics];
         let s = scope
             .iter()
-            .map(|x| x.into())
+            .map(Scope::as_str)
             .collect::<Vec<&str>>()
             .join(",");
         assert_eq!(s, "user.info,user.metrics");
+
+        use strum::IntoEnumIterator;
+        let scopes = Scope::iter().map(<&'static str>::from).collect::<Vec<_>>();
+        assert_eq!(
+            scopes,
+            [
+                "user.info",
+                "user.metrics",
+                "user.activity",
+                "user.sleepevents",
+                "Unknown"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_unknown_scope() -> anyhow::Result<()> {
+        let token: AccessToken = serde_json::from_value(json!({
+            "userid": "363",
+            "access_token": "a075f8c14fb8df40b08ebc8508533dc332a6910a",
+            "refresh_token": "f631236f02b991810feb774765b6ae8e6c6839ca",
+            "expires_in": "10800",
+            "scope": "user.info,user.metrics,user.sleepevents,user.unknown",
+            "token_type": "Bearer"
+        }))?;
+        assert_eq!(token.user_id, 363);
+        assert_eq!(token.expires_in, 10800);
+        assert_eq!(
+            token.scope,
+            vec![
+                Scope::UserInfo,
+                Scope::UserMetrics,
+                Scope::UserSleepEvents,
+                Scope::Unknown("user.unknown".into())
+            ]
+        );
+        assert_eq!(token.scope[3].to_string(), "user.unknown");
+
+        let token: RefreshToken = serde_json::from_value(json!({
+            "userid": 363,
+            "access_token": "a075f8c14fb8df40b08ebc8508533dc332a6910a",
+            "refresh_token": "f631236f02b991810feb774765b6ae8e6c6839ca",
+            "expires_in": 10800,
+            "scope": "",
+            "token_type": "Bearer"
+        }))?;
+        assert_eq!(token.scope, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_access_token_request() -> anyhow::Result<()> {
+        let req = AccessTokenRequest {
+            action: "requesttoken".into(),
+            grant_type: "authorization_code".into(),
+            client_id: "test_client_id".into(),
+            client_secret: "test_consumer_secret".into(),
+            redirect_uri: "https://localhost/callback".into(),
+            code: "test_code".into(),
+        };
+        assert_eq!(
+            serde_urlencoded::to_string(&req)?,
+            "action=requesttoken&grant_type=authorization_code&client_id=test_client_id\
+             &client_secret=test_consumer_secret&redirect_uri=https%3A%2F%2Flocalhost%2Fcallback\
+             &code=test_code"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_refresh_token_request() -> anyhow::Result<()> {
+        let req = RefreshTokenRequest {
+            action: "requesttoken".into(),
+            grant_type: "refresh_token".into(),
+            client_id: "test_client_id".into(),
+            client_secret: "test_consumer_secret".into(),
+            refresh_token: "test_refresh_token".into(),
+        };
+        assert_eq!(
+            serde_urlencoded::to_string(&req)?,
+            "action=requesttoken&grant_type=refresh_token&client_id=test_client_id\
+             &client_secret=test_consumer_secret&refresh_token=test_refresh_token"
+        );
         Ok(())
     }
 
@@ -232,8 +480,8 @@ mod tests {
         "test_consumer_secret".into(),
         "https://localhost".into(),
         vec![Scope::UserInfo, Scope::UserMetrics],
-        Some("mode".into()),
-        "https://account.withings.com/oauth2_user/authorize2?response_type=code&client_id=test_client_id&redirect_uri=https%3A%2F%2Flocalhost&scope=user.info%2Cuser.metrics&state=mode"
+        Some("demo".into()),
+        "https://account.withings.com/oauth2_user/authorize2?response_type=code&client_id=test_client_id&redirect_uri=https%3A%2F%2Flocalhost&scope=user.info%2Cuser.metrics&mode=demo&state=test_state"
     )]
     #[case(
         "test_client_id".into(),
@@ -241,7 +489,7 @@ mod tests {
         "https://localhost".into(),
         vec![],
         None,
-        "https://account.withings.com/oauth2_user/authorize2?response_type=code&client_id=test_client_id&redirect_uri=https%3A%2F%2Flocalhost&state=dev"
+        "https://account.withings.com/oauth2_user/authorize2?response_type=code&client_id=test_client_id&redirect_uri=https%3A%2F%2Flocalhost&state=test_state"
     )]
     fn test_get_authorize_url(
         #[case] test_client_id: String,
@@ -260,10 +508,96 @@ mod tests {
             mode,
         );
 
-        assert_eq!(client.get_authorize_url().unwrap(), expected);
+        let state = State::new("test_state".into());
+        assert_eq!(client.get_authorize_url(&state).unwrap(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_state() {
+        let state = State::random();
+        assert_eq!(state.as_str().len(), 64);
+        assert_ne!(state, State::random());
+    }
+
+    #[test]
+    fn test_verify_state() {
+        let state = State::new("test_state".into());
+        assert_matches!(state.verify("test_state"), Ok(()));
+        assert_matches!(
+            state.verify("other_state"),
+            Err(AuthorizationError::StateMismatch)
+        );
+        assert_matches!(
+            state.verify("test_stat"),
+            Err(AuthorizationError::StateMismatch)
+        );
+        assert_matches!(state.verify(""), Err(AuthorizationError::StateMismatch));
+    }
+
+    #[rstest]
+    #[case("https://localhost/callback?code=test_code&state=test_state")]
+    #[case("https://localhost/callback?state=test_state&code=test_code#fragment")]
+    #[case("?code=test_code&state=test_state")]
+    #[case("code=test_code&state=test_state")]
+    fn test_parse_callback(#[case] redirect: &str) -> anyhow::Result<()> {
+        let client = AuthCli::new(
+            WITHINGS_API_URL.into(),
+            "test_client_id".into(),
+            "test_consumer_secret".into(),
+            "https://localhost/callback".into(),
+            vec![],
+            None,
+        );
+
+        let state = State::new("test_state".into());
+        assert_eq!(
+            client.parse_callback(redirect, &state)?.expose(),
+            "test_code"
+        );
         Ok(())
     }
 
+    #[test]
+    fn test_parse_callback_errors() {
+        let client = AuthCli::new(
+            WITHINGS_API_URL.into(),
+            "test_client_id".into(),
+            "test_consumer_secret".into(),
+            "https://localhost/callback".into(),
+            vec![],
+            None,
+        );
+        let state = State::new("test_state".into());
+
+        let err = client
+            .parse_callback(
+                "https://localhost/callback?error=access_denied&error_description=User+refused&state=test_state",
+                &state,
+            )
+            .unwrap_err();
+        assert_matches!(
+            err,
+            Error::Authorization(AuthorizationError::AuthorizationDenied { error, description }) => {
+                assert_eq!(error, "access_denied");
+                assert_eq!(description.as_deref(), Some("User refused"));
+            }
+        );
+
+        let err = client
+            .parse_callback("code=test_code&state=other_state", &state)
+            .unwrap_err();
+        assert_matches!(err, Error::Authorization(AuthorizationError::StateMismatch));
+
+        let err = client.parse_callback("code=test_code", &state).unwrap_err();
+        assert_matches!(err, Error::Authorization(AuthorizationError::StateMismatch));
+
+        let err = client
+            .parse_callback("state=test_state", &state)
+            .unwrap_err();
+        assert_matches!(err, Error::Authorization(AuthorizationError::MissingCode));
+    }
+
     #[test]
     fn test_desrialize_access_token_response() -> anyhow::Result<()> {
         let json = serde_json::to_string(&json!({
@@ -283,14 +617,15 @@ mod tests {
             data,
             AccessTokenResponse {
                 status: 0,
-                body: AccessToken {
+                body: Some(AccessToken {
                     user_id: 363,
                     access_token: "test_access_token".into(),
                     refresh_token: "test_refresh_token".into(),
                     expires_in: 10800,
                     scope: vec![Scope::UserInfo, Scope::UserMetrics],
                     token_type: "Bearer".into()
-                }
+                }),
+                error: None,
             }
         );
 
@@ -335,16 +670,13 @@ mod tests {
             }))?)
             .create();
 
-        let response_body = AccessTokenResponse {
-            status: 0,
-            body: AccessToken {
-                user_id: 363,
-                access_token: "test_access_token".into(),
-                refresh_token: "test_refresh_token".into(),
-                expires_in: 10800,
-                scope: vec![Scope::UserInfo, Scope::UserMetrics],
-                token_type: "Bearer".into(),
-            },
+        let response_body = AccessToken {
+            user_id: 363,
+            access_token: "test_access_token".into(),
+            refresh_token: "test_refresh_token".into(),
+            expires_in: 10800,
+            scope: vec![Scope::UserInfo, Scope::UserMetrics],
+            token_type: "Bearer".into(),
         };
 
         let res = client.get_access_token(code).await?;
@@ -383,12 +715,8 @@ mod tests {
 
         let res = client.get_access_token(code).await;
 
-        assert_matches!(res, Err(err) => {
-            if let Some(WithingsApiError::InternalServerError{status, ..}) = err.downcast_ref::<WithingsApiError>() {
-                assert_eq!(*status, StatusCode::INTERNAL_SERVER_ERROR);
-            } else {
-                panic!("is not WithingsError");
-            }
+        assert_matches!(res, Err(Error::HttpStatus { status, .. }) => {
+            assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
         });
 
         mock.assert();
@@ -396,6 +724,51 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_get_access_token_retry() -> anyhow::Result<()> {
+        let client = AuthCli::new(
+            mockito::server_url(),
+            "test_client_id".into(),
+            "test_consumer_secret".into(),
+            "https://localhost".into(),
+            vec![Scope::UserInfo, Scope::UserMetrics],
+            None,
+        );
+        let policy = RetryPolicy {
+            initial_backoff: std::time::Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        let mock = mockito::mock("POST", AUTH2_TOKEN_PATH)
+            .with_status(503)
+            .expect(1)
+            .create();
+        let res = client
+            .clone()
+            .with_retry_policy(policy.clone())
+            .get_access_token("sample_authorization_code")
+            .await;
+        assert_matches!(res, Err(Error::HttpStatus { .. }));
+        mock.assert();
+        drop(mock);
+
+        let mock = mockito::mock("POST", AUTH2_TOKEN_PATH)
+            .with_status(503)
+            .expect(3)
+            .create();
+        let res = client
+            .with_retry_policy(RetryPolicy {
+                idempotent_only: false,
+                ..policy
+            })
+            .get_access_token("sample_authorization_code")
+            .await;
+        assert_matches!(res, Err(Error::HttpStatus { .. }));
+        mock.assert();
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_get_access_token_invalid_params_error() -> anyhow::Result<()> {
         let client = AuthCli::new(
@@ -429,12 +802,8 @@ mod tests {
 
         let res = client.get_access_token(code).await;
 
-        assert_matches!(res, Err(err) => {
-            if let Some(WithingsApiError::InvalidParams{status, ..}) = err.downcast_ref::<WithingsApiError>() {
-                assert_eq!(*status, 503);
-            } else {
-                panic!("is not WithingsApiError");
-            }
+        assert_matches!(res, Err(Error::Api(WithingsApiError::InvalidParams { status, .. })) => {
+            assert_eq!(status, 503);
         });
 
         mock.assert();
@@ -442,6 +811,122 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_authorize_flow_against_local_account_server() -> anyhow::Result<()> {
+        let client = AuthCli::new(
+            mockito::server_url(),
+            "test_client_id".into(),
+            "test_consumer_secret".into(),
+            "https://localhost".into(),
+            vec![Scope::UserInfo, Scope::UserMetrics],
+            None,
+        )
+        .with_account_url(format!("{}/", mockito::server_url()));
+
+        let authorize = mockito::mock("GET", AUTHORIZE_PATH)
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("client_id".into(), "test_client_id".into()),
+                mockito::Matcher::UrlEncoded("redirect_uri".into(), "https://localhost".into()),
+            ]))
+            .with_status(302)
+            .with_header(
+                "location",
+                "https://localhost/?code=sample_authorization_code&state=test_state",
+            )
+            .create();
+
+        let token = mockito::mock("POST", AUTH2_TOKEN_PATH)
+            .with_status(200)
+            .match_body(mockito::Matcher::UrlEncoded(
+                "code".into(),
+                "sample_authorization_code".into(),
+            ))
+            .with_body(serde_json::to_string(&json!({
+                "status": 0,
+                "body": {
+                    "userid": 363,
+                    "access_token": "test_access_token",
+                    "refresh_token": "test_refresh_token",
+                    "expires_in": 10800,
+                    "scope": "user.info,user.metrics",
+                    "token_type": "Bearer"
+                }
+            }))?)
+            .create();
+
+        let browser = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let state = State::new("test_state".into());
+        let res = browser
+            .get(client.get_authorize_url(&state)?)
+            .send()
+            .await?;
+        let location = res.headers()[reqwest::header::LOCATION].to_str()?;
+
+        let res = client.exchange_callback(location, &state).await?;
+        assert_eq!(res.access_token.expose(), "test_access_token");
+        authorize.assert();
+        token.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_errors_do_not_leak_secrets() -> anyhow::Result<()> {
+        let secrets = [
+            "super_secret_consumer",
+            "super_secret_code",
+            "super_secret_refresh",
+        ];
+        let client = AuthCli::new(
+            mockito::server_url(),
+            "test_client_id".into(),
+            "super_secret_consumer".into(),
+            "https://localhost".into(),
+            vec![Scope::UserInfo],
+            None,
+        );
+
+        let mut outputs = vec![format!("{:?}", client)];
+        for (status, body) in [
+            (500, "Internal Server Error".to_string()),
+            (
+                200,
+                serde_json::to_string(
+                    &json!({"status": 503, "body": {}, "error": "Invalid params"}),
+                )?,
+            ),
+            (200, serde_json::to_string(&json!({"body": {}}))?),
+            (200, "not json".to_string()),
+        ] {
+            let _mock = mockito::mock("POST", AUTH2_TOKEN_PATH)
+                .with_status(status)
+                .with_body(body)
+                .create();
+
+            let err = client
+                .get_access_token("super_secret_code")
+                .await
+                .unwrap_err();
+            outputs.push(format!("{} {:?}", err, err));
+
+            let err = client
+                .get_refresh_token("super_secret_refresh")
+                .await
+                .unwrap_err();
+            outputs.push(format!("{} {:?}", err, err));
+        }
+
+        for output in outputs {
+            for secret in secrets {
+                assert!(!output.contains(secret), "{} leaked in {}", secret, output);
+            }
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_get_refresh_token() -> anyhow::Result<()> {
         let client = AuthCli::new(
@@ -479,16 +964,13 @@ mod tests {
             }))?)
             .create();
 
-        let response_body = RefreshTokenResponse {
-            status: 0,
-            body: RefreshToken {
-                user_id: 363,
-                access_token: "test_access_token".into(),
-                refresh_token: "test_refresh_token".into(),
-                expires_in: 10800,
-                scope: vec![Scope::UserInfo, Scope::UserMetrics],
-                token_type: "Bearer".into(),
-            },
+        let response_body = RefreshToken {
+            user_id: 363,
+            access_token: "test_access_token".into(),
+            refresh_token: "test_refresh_token".into(),
+            expires_in: 10800,
+            scope: vec![Scope::UserInfo, Scope::UserMetrics],
+            token_type: "Bearer".into(),
         };
 
         let res = client.get_refresh_token(refresh_token).await?;
@@ -0,0 +1,320 @@
+use std::process::Command;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use url::Url;
+
+use crate::auth::cli::{AccessToken, AuthCli, State};
+use crate::error::AuthorizationError;
+use crate::{Error, Result};
+
+/// Default deadline of `login_with_loopback`, see `AuthCli::with_login_timeout`.
+pub const DEFAULT_LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+/// Time a connection gets to send its request line, since browsers open
+/// speculative connections they may never use.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SUCCESS_PAGE: &str =
+    "<html><body>Withings authorization finished. You can close this window.</body></html>";
+
+impl AuthCli {
+    /// Interactive login for command-line tools.
+    ///
+    /// Listens on `callback_uri`, which must be a plain `http` localhost URL
+    /// registered for the app, hands the authorize URL to `on_authorize_url`,
+    /// waits for the redirect and exchanges the code for a token. Fails with
+    /// `AuthorizationError::LoginTimedOut` when no callback arrives within
+    /// `login_timeout`.
+    pub async fn login_with_loopback<F>(&self, on_authorize_url: F) -> Result<AccessToken>
+    where
+        F: FnOnce(&str),
+    {
+        let callback = Url::parse(&self.callback_uri)?;
+        let host = match (callback.scheme(), callback.host_str()) {
+            ("http", Some(host @ ("localhost" | "127.0.0.1" | "[::1]"))) => host,
+            _ => {
+                return Err(
+                    AuthorizationError::UnsupportedCallbackUri(self.callback_uri.clone()).into(),
+                )
+            }
+        };
+        let port = callback.port_or_known_default().unwrap_or(80);
+        let listener =
+            TcpListener::bind((host.trim_matches(|c| c == '[' || c == ']'), port)).await?;
+
+        let state = State::random();
+        on_authorize_url(&self.get_authorize_url(&state)?);
+
+        // Connections are read concurrently, so one that never sends a
+        // request doesn't hold back the callback.
+        let (tx, mut rx) = mpsc::channel(16);
+        let acceptor = tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    if let Ok(Ok(target)) =
+                        tokio::time::timeout(READ_TIMEOUT, read_request_target(&mut stream)).await
+                    {
+                        let _ = tx.send((stream, target)).await;
+                    }
+                });
+            }
+        });
+
+        // Only a callback carrying our state ends the login, so that a stale
+        // tab, a prefetch or a dropped connection doesn't abort it.
+        let callback = async {
+            while let Some((mut stream, target)) = rx.recv().await {
+                let target = match target {
+                    Some(target) if target.split('?').next() == Some(callback.path()) => target,
+                    _ => {
+                        let _ = respond(&mut stream, "404 Not Found", "").await;
+                        continue;
+                    }
+                };
+
+                let res = self.parse_callback(&target, &state);
+                let mismatch = matches!(
+                    res,
+                    Err(Error::Authorization(AuthorizationError::StateMismatch))
+                );
+                let page = match &res {
+                    Ok(_) => SUCCESS_PAGE.to_string(),
+                    Err(e) => format!(
+                        "<html><body>Withings authorization failed: {}</body></html>",
+                        escape_html(&e.to_string())
+                    ),
+                };
+                let status = if mismatch {
+                    "400 Bad Request"
+                } else {
+                    "200 OK"
+                };
+                let _ = respond(&mut stream, status, &page).await;
+                if !mismatch {
+                    return res;
+                }
+            }
+            Err(AuthorizationError::LoginTimedOut.into())
+        };
+        let res = tokio::time::timeout(self.login_timeout, callback).await;
+        acceptor.abort();
+        let code = res.map_err(|_| AuthorizationError::LoginTimedOut)??;
+
+        self.get_access_token(code.expose()).await
+    }
+}
+
+/// `on_authorize_url` handler printing the URL for the user to open.
+pub fn print_authorize_url(url: &str) {
+    eprintln!(
+        "Open the following URL in your browser to authorize access:\n\n    {}\n",
+        url
+    );
+}
+
+/// `on_authorize_url` handler opening the system browser, falling back to
+/// printing the URL.
+pub fn open_authorize_url(url: &str) {
+    let opened = if cfg!(target_os = "macos") {
+        Command::new("open").arg(url).status()
+    } else if cfg!(target_os = "windows") {
+        // `cmd /C start` would split the URL at its first `&`.
+        Command::new("rundll32")
+            .args(["url.dll,FileProtocolHandler", url])
+            .status()
+    } else {
+        Command::new("xdg-open").arg(url).status()
+    };
+
+    if !matches!(opened, Ok(status) if status.success()) {
+        print_authorize_url(url);
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+async fn read_request_target(stream: &mut TcpStream) -> Result<Option<String>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < MAX_REQUEST_SIZE {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let req = String::from_utf8_lossy(&buf);
+    let mut parts = req.lines().next().unwrap_or_default().split_whitespace();
+    Ok(match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Some(target.to_string()),
+        _ => None,
+    })
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> Result<()> {
+    let res = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(res.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::cli::Scope;
+    use crate::WITHINGS_API_URL;
+    use assert_matches::assert_matches;
+    use serde_json::json;
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[tokio::test]
+    async fn test_login_with_loopback() -> anyhow::Result<()> {
+        let callback_uri = format!("http://127.0.0.1:{}/callback", free_port());
+        let client = AuthCli::new(
+            mockito::server_url(),
+            "test_client_id".into(),
+            "test_consumer_secret".into(),
+            callback_uri.clone(),
+            vec![Scope::UserInfo, Scope::UserMetrics],
+            None,
+        );
+
+        let mock = mockito::mock("POST", "/v2/oauth2")
+            .with_status(200)
+            .match_body(mockito::Matcher::UrlEncoded(
+                "code".into(),
+                "sample_authorization_code".into(),
+            ))
+            .with_body(serde_json::to_string(&json!({
+                "status": 0,
+                "body": {
+                    "userid": 363,
+                    "access_token": "test_access_token",
+                    "refresh_token": "test_refresh_token",
+                    "expires_in": 10800,
+                    "scope": "user.info,user.metrics",
+                    "token_type": "Bearer"
+                }
+            }))?)
+            .create();
+
+        let browser = |url: &str| {
+            let url = Url::parse(url).unwrap();
+            let state = url
+                .query_pairs()
+                .find(|(k, _)| k == "state")
+                .map(|(_, v)| v.into_owned())
+                .unwrap();
+            tokio::spawn(async move {
+                // A speculative connection that never sends doesn't block the
+                // others.
+                let addr = Url::parse(&callback_uri)
+                    .unwrap()
+                    .socket_addrs(|| None)
+                    .unwrap();
+                let _idle = TcpStream::connect(addr[0]).await.unwrap();
+
+                let favicon = format!("{}/favicon.ico", callback_uri.trim_end_matches("/callback"));
+                let res = reqwest::get(favicon).await.unwrap();
+                assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+
+                // A stale tab hitting the callback doesn't end the login.
+                let res = reqwest::get(format!("{}?code=stale_code&state=stale", callback_uri))
+                    .await
+                    .unwrap();
+                assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+                let res = reqwest::get(format!(
+                    "{}?code=sample_authorization_code&state={}",
+                    callback_uri, state
+                ))
+                .await
+                .unwrap();
+                assert_eq!(res.status(), reqwest::StatusCode::OK);
+            });
+        };
+
+        let res = client.login_with_loopback(browser).await?;
+        assert_eq!(res.access_token.expose(), "test_access_token");
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_login_with_loopback_times_out() {
+        let client = AuthCli::new(
+            mockito::server_url(),
+            "test_client_id".into(),
+            "test_consumer_secret".into(),
+            format!("http://127.0.0.1:{}/callback", free_port()),
+            vec![],
+            None,
+        )
+        .with_login_timeout(std::time::Duration::from_millis(100));
+
+        let res = client.login_with_loopback(|_| ()).await;
+        assert_matches!(
+            res,
+            Err(Error::Authorization(AuthorizationError::LoginTimedOut))
+        );
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html(r#"<script>alert("x")</script> & 'y'"#),
+            "&lt;script&gt;alert(&quot;x&quot;)&lt;/script&gt; &amp; &#39;y&#39;"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_with_loopback_rejects_remote_callback() {
+        let client = AuthCli::new(
+            WITHINGS_API_URL.into(),
+            "test_client_id".into(),
+            "test_consumer_secret".into(),
+            "https://example.com/callback".into(),
+            vec![],
+            None,
+        );
+
+        let res = client
+            .login_with_loopback(|_| panic!("must not be called"))
+            .await;
+        assert_matches!(
+            res,
+            Err(Error::Authorization(
+                AuthorizationError::UnsupportedCallbackUri(_)
+            ))
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::api::cli::ApiCli;
+use crate::sync::{fetch, now, Batch, Change, ChangeKind, Query, Service, Sink};
+use crate::Result;
+
+const DAY: u64 = 86400;
+
+/// Where a backfill stands for one user and service. Windows are
+/// `[next, next + window)` in epoch seconds, up to `until`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub next: u64,
+    pub until: u64,
+    /// Page offset within the window starting at `next`.
+    pub offset: Option<u64>,
+}
+
+impl Checkpoint {
+    pub fn is_done(&self) -> bool {
+        self.next >= self.until
+    }
+}
+
+pub trait CheckpointStore {
+    fn load(
+        &self,
+        user_id: u64,
+        service: Service,
+    ) -> impl Future<Output = Result<Option<Checkpoint>>> + Send;
+
+    fn save(
+        &self,
+        user_id: u64,
+        service: Service,
+        checkpoint: Checkpoint,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryCheckpointStore {
+    checkpoints: Mutex<HashMap<(u64, Service), Checkpoint>>,
+}
+
+impl CheckpointStore for MemoryCheckpointStore {
+    async fn load(&self, user_id: u64, service: Service) -> Result<Option<Checkpoint>> {
+        Ok(self
+            .checkpoints
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(user_id, service))
+            .copied())
+    }
+
+    async fn save(&self, user_id: u64, service: Service, checkpoint: Checkpoint) -> Result<()> {
+        self.checkpoints
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((user_id, service), checkpoint);
+        Ok(())
+    }
+}
+
+/// Reported after every page written to the sink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackfillProgress {
+    pub user_id: u64,
+    pub service: Service,
+    pub checkpoint: Checkpoint,
+    /// Start of the whole backfill.
+    pub since: u64,
+    /// Records written by this run so far.
+    pub records: usize,
+}
+
+impl BackfillProgress {
+    /// Share of the history walked, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        let total = self.checkpoint.until.saturating_sub(self.since);
+        if total == 0 {
+            return 1.0;
+        }
+        let done = self.checkpoint.next.saturating_sub(self.since).min(total);
+        done as f64 / total as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackfillReport {
+    pub service: Service,
+    pub records: usize,
+    pub pages: usize,
+    /// End of the backfilled history, from where `SyncEngine` can take over
+    /// as its cursor.
+    pub until: u64,
+}
+
+type ProgressFn = Arc<dyn Fn(&BackfillProgress) + Send + Sync>;
+
+/// Walks the complete history of a user, window by window, and writes every
+/// record to a [`Sink`] as `Inserted`.
+///
+/// A checkpoint is saved after each acknowledged page, so running the job
+/// again after a crash resumes from the page that failed. Requests go
+/// through the rate limiter of the given `ApiCli`, e.g. one from
+/// `WithingsClient::api`.
+pub struct BackfillJob<K, S> {
+    api: ApiCli,
+    user_id: u64,
+    services: Vec<Service>,
+    since: u64,
+    until: Option<u64>,
+    window: Duration,
+    checkpoints: K,
+    sink: S,
+    progress: Option<ProgressFn>,
+}
+
+impl<K: CheckpointStore, S: Sink> BackfillJob<K, S> {
+    pub fn new(api: ApiCli, user_id: u64, checkpoints: K, sink: S) -> BackfillJob<K, S> {
+        BackfillJob {
+            api: api.with_user_id(user_id),
+            user_id,
+            services: Service::ALL.to_vec(),
+            // Withings was founded in 2008.
+            since: 1199145600,
+            until: None,
+            window: Duration::from_secs(365 * DAY),
+            checkpoints,
+            sink,
+            progress: None,
+        }
+    }
+
+    pub fn with_services(mut self, services: Vec<Service>) -> BackfillJob<K, S> {
+        self.services = services;
+        self
+    }
+
+    /// Start of the history, rounded down to a UTC day.
+    pub fn with_since(mut self, since: u64) -> BackfillJob<K, S> {
+        self.since = since - since % DAY;
+        self
+    }
+
+    /// End of the history for a new backfill, `now` by default. A resumed
+    /// backfill keeps the end of its checkpoint.
+    pub fn with_until(mut self, until: u64) -> BackfillJob<K, S> {
+        self.until = Some(until);
+        self
+    }
+
+    /// Length of each window, rounded up to whole days since activity and
+    /// sleep are queried by date.
+    pub fn with_window(mut self, window: Duration) -> BackfillJob<K, S> {
+        let days = window.as_secs().div_ceil(DAY).max(1);
+        self.window = Duration::from_secs(days * DAY);
+        self
+    }
+
+    pub fn on_progress<F>(mut self, progress: F) -> BackfillJob<K, S>
+    where
+        F: Fn(&BackfillProgress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    pub fn checkpoints(&self) -> &K {
+        &self.checkpoints
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Backfill every selected service in turn, stopping at the first error.
+    pub async fn run(&self) -> Result<Vec<BackfillReport>> {
+        let mut reports = Vec::with_capacity(self.services.len());
+        for &service in &self.services {
+            reports.push(self.backfill(service).await?);
+        }
+        Ok(reports)
+    }
+
+    pub async fn backfill(&self, service: Service) -> Result<BackfillReport> {
+        let mut checkpoint = match self.checkpoints.load(self.user_id, service).await? {
+            Some(checkpoint) => checkpoint,
+            None => {
+                let checkpoint = Checkpoint {
+                    next: self.since,
+                    until: self.until.unwrap_or_else(now),
+                    offset: None,
+                };
+                self.checkpoints
+                    .save(self.user_id, service, checkpoint)
+                    .await?;
+                checkpoint
+            }
+        };
+        let mut report = BackfillReport {
+            service,
+            records: 0,
+            pages: 0,
+            until: checkpoint.until,
+        };
+
+        while !checkpoint.is_done() {
+            let end = (checkpoint.next + self.window.as_secs()).min(checkpoint.until);
+            let query = Query::Window {
+                start: checkpoint.next,
+                end,
+            };
+            let page = fetch(&self.api, service, query, checkpoint.offset).await?;
+            report.pages += 1;
+            report.records += page.records.len();
+
+            if !page.records.is_empty() {
+                self.sink
+                    .write(Batch {
+                        user_id: self.user_id,
+                        service,
+                        changes: page
+                            .records
+                            .into_iter()
+                            .map(|record| Change::Upsert {
+                                kind: ChangeKind::Inserted,
+                                record: Box::new(record),
+                            })
+                            .collect(),
+                    })
+                    .await?;
+            }
+
+            checkpoint = if page.more && checkpoint.offset != Some(page.offset) {
+                Checkpoint {
+                    offset: Some(page.offset),
+                    ..checkpoint
+                }
+            } else {
+                Checkpoint {
+                    next: end,
+                    offset: None,
+                    ..checkpoint
+                }
+            };
+            self.checkpoints
+                .save(self.user_id, service, checkpoint)
+                .await?;
+
+            if let Some(progress) = &self.progress {
+                progress(&BackfillProgress {
+                    user_id: self.user_id,
+                    service,
+                    checkpoint,
+                    since: self.since,
+                    records: report.records,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::{RateLimit, RateLimiter};
+    use crate::sync::Record;
+    use crate::test_util::{api, group, mock};
+    use crate::Error;
+    use assert_matches::assert_matches;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Fails once on the batch holding `fail_grpid`.
+    #[derive(Default)]
+    struct FlakySink {
+        grpids: Mutex<Vec<u64>>,
+        fail_grpid: u64,
+        failed: AtomicBool,
+    }
+
+    impl Sink for FlakySink {
+        async fn write(&self, batch: Batch) -> Result<()> {
+            let mut grpids = Vec::new();
+            for change in batch.changes {
+                if let Change::Upsert { record, .. } = change {
+                    if let Record::Measure(grp) = *record {
+                        grpids.push(grp.grpid);
+                    }
+                }
+            }
+            if grpids.contains(&self.fail_grpid) && !self.failed.swap(true, Ordering::SeqCst) {
+                return Err(Error::Storage("sink is down".into()));
+            }
+            self.grpids.lock().unwrap().extend(grpids);
+            Ok(())
+        }
+
+        async fn known_groups(&self, _user_id: u64, _start: u64, _end: u64) -> Result<Vec<u64>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backfill_resumes_from_checkpoint() -> anyhow::Result<()> {
+        let path = "/backfill_resume/measure";
+        let since = 100 * DAY;
+        let window = 10 * DAY;
+        let first = mock(
+            path,
+            &format!(
+                "action=getmeas&startdate={}&enddate={}",
+                since,
+                since + window - 1
+            ),
+            json!({"measuregrps": [group(1, since + DAY)], "more": 1, "offset": 1}),
+        );
+        let first_page2 = mock(
+            path,
+            &format!(
+                "action=getmeas&startdate={}&enddate={}&offset=1",
+                since,
+                since + window - 1
+            ),
+            json!({"measuregrps": [group(2, since + 2 * DAY)], "more": 0}),
+        );
+        // The last window is shorter and fetched again after the failure.
+        let second = mock(
+            path,
+            &format!(
+                "action=getmeas&startdate={}&enddate={}",
+                since + window,
+                since + 15 * DAY - 1
+            ),
+            json!({"measuregrps": [group(3, since + 12 * DAY)]}),
+        )
+        .expect(2);
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let recorded = progress.clone();
+        let job = BackfillJob::new(
+            api("/backfill_resume"),
+            363,
+            MemoryCheckpointStore::default(),
+            FlakySink {
+                fail_grpid: 3,
+                ..Default::default()
+            },
+        )
+        .with_services(vec![Service::Measure])
+        .with_since(since + 3600)
+        .with_until(since + 15 * DAY)
+        .with_window(Duration::from_secs(window))
+        .on_progress(move |p| recorded.lock().unwrap().push(p.fraction()));
+
+        assert_matches!(job.run().await, Err(Error::Storage(_)));
+        assert_eq!(
+            job.checkpoints().load(363, Service::Measure).await?,
+            Some(Checkpoint {
+                next: since + window,
+                until: since + 15 * DAY,
+                offset: None,
+            })
+        );
+
+        let reports = job.run().await?;
+        assert_eq!(
+            reports,
+            vec![BackfillReport {
+                service: Service::Measure,
+                records: 1,
+                pages: 1,
+                until: since + 15 * DAY,
+            }]
+        );
+        assert_eq!(*job.sink().grpids.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(*progress.lock().unwrap(), vec![0.0, 2.0 / 3.0, 1.0]);
+        first.assert();
+        first_page2.assert();
+        second.assert();
+
+        // A finished backfill doesn't fetch anything.
+        assert_eq!(job.backfill(Service::Measure).await?.pages, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backfill_respects_rate_limiter() -> anyhow::Result<()> {
+        let path = "/backfill_limited/v2/measure";
+        let mocks = ["2022-01-01", "2022-01-02", "2022-01-03"]
+            .iter()
+            .map(|date| {
+                mock(
+                    path,
+                    &format!(
+                        "action=getactivity&startdateymd={}&enddateymd={}",
+                        date, date
+                    ),
+                    json!({"activities": [{"date": date, "steps": 1000}], "more": false, "offset": 0}),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let limiter = RateLimiter::new(RateLimit {
+            requests_per_minute: 120,
+            per_user_requests_per_minute: Some(1),
+        });
+        let waits = Arc::new(Mutex::new(Vec::new()));
+        let recorded = waits.clone();
+        limiter.set_observer(move |w| recorded.lock().unwrap().push(w.user_id));
+
+        // 2022-01-01T00:00:00Z
+        let since = 1640995200;
+        let job = BackfillJob::new(
+            api("/backfill_limited").with_rate_limiter(limiter),
+            363,
+            MemoryCheckpointStore::default(),
+            FlakySink::default(),
+        )
+        .with_services(vec![Service::Activity])
+        .with_since(since)
+        .with_until(since + 3 * DAY)
+        .with_window(Duration::from_secs(1));
+
+        let report = job.backfill(Service::Activity).await?;
+        assert_eq!((report.records, report.pages), (3, 3));
+        assert_eq!(*waits.lock().unwrap(), vec![Some(363), Some(363)]);
+        assert_matches!(
+            job.checkpoints().load(363, Service::Activity).await?,
+            Some(Checkpoint { next, offset: None, .. }) if next == since + 3 * DAY
+        );
+        for mock in mocks {
+            mock.assert();
+        }
+
+        Ok(())
+    }
+}
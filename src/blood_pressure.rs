@@ -0,0 +1,377 @@
+use std::time::Duration;
+
+use crate::api::measure::{Category, MeasureGroup, MeasureType};
+
+const DAY: u64 = 86400;
+
+/// Blood pressure category of the 2017 ACC/AHA guideline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AhaCategory {
+    /// Below 120/80.
+    Normal,
+    /// Systolic 120-129 and diastolic below 80.
+    Elevated,
+    /// Systolic 130-139 or diastolic 80-89.
+    Stage1,
+    /// Systolic 140 or more, or diastolic 90 or more.
+    Stage2,
+    /// Systolic above 180 and/or diastolic above 120.
+    Crisis,
+}
+
+/// Blood pressure grade of the 2018 ESC/ESH guidelines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EscGrade {
+    /// Below 120/80.
+    Optimal,
+    /// 120-129 and/or 80-84.
+    Normal,
+    /// 130-139 and/or 85-89.
+    HighNormal,
+    /// 140-159 and/or 90-99.
+    Grade1,
+    /// 160-179 and/or 100-109.
+    Grade2,
+    /// 180 or more and/or 110 or more.
+    Grade3,
+}
+
+/// Systolic and diastolic pressures in mmHg, with the heart pulse in bpm
+/// when measured alongside.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloodPressureReading {
+    pub grpid: u64,
+    pub date: u64,
+    pub systolic: f64,
+    pub diastolic: f64,
+    pub heart_pulse: Option<f64>,
+    pub deviceid: Option<String>,
+}
+
+impl BloodPressureReading {
+    /// `None` unless `grp` is a real measure group with both pressures.
+    pub fn from_group(grp: &MeasureGroup) -> Option<BloodPressureReading> {
+        if grp.category != Category::Real {
+            return None;
+        }
+        let value = |t: MeasureType| {
+            grp.measures
+                .iter()
+                .find(|m| m.meastype == t)
+                .map(|m| m.real_value())
+        };
+        Some(BloodPressureReading {
+            grpid: grp.grpid,
+            date: grp.date,
+            systolic: value(MeasureType::SystolicBloodPressure)?,
+            diastolic: value(MeasureType::DiastolicBloodPressure)?,
+            heart_pulse: value(MeasureType::HeartPulse),
+            deviceid: grp.deviceid.clone(),
+        })
+    }
+
+    /// Readings of the groups that have one, oldest first.
+    pub fn from_groups(groups: &[MeasureGroup]) -> Vec<BloodPressureReading> {
+        let mut readings = groups
+            .iter()
+            .filter_map(BloodPressureReading::from_group)
+            .collect::<Vec<_>>();
+        readings.sort_by_key(|r| r.date);
+        readings
+    }
+
+    pub fn aha_category(&self) -> AhaCategory {
+        aha_category(self.systolic, self.diastolic)
+    }
+
+    pub fn esc_grade(&self) -> EscGrade {
+        esc_grade(self.systolic, self.diastolic)
+    }
+}
+
+/// Mean of several readings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloodPressureAverage {
+    pub systolic: f64,
+    pub diastolic: f64,
+    /// Mean of the readings that have one.
+    pub heart_pulse: Option<f64>,
+    /// Readings averaged.
+    pub readings: usize,
+    /// Dates of the first and last reading averaged.
+    pub start: u64,
+    pub end: u64,
+}
+
+impl BloodPressureAverage {
+    pub fn aha_category(&self) -> AhaCategory {
+        aha_category(self.systolic, self.diastolic)
+    }
+
+    pub fn esc_grade(&self) -> EscGrade {
+        esc_grade(self.systolic, self.diastolic)
+    }
+}
+
+/// Plain mean of `readings`, `None` if empty.
+pub fn average(readings: &[BloodPressureReading]) -> Option<BloodPressureAverage> {
+    let n = readings.len() as f64;
+    let pulses = readings
+        .iter()
+        .filter_map(|r| r.heart_pulse)
+        .collect::<Vec<_>>();
+    Some(BloodPressureAverage {
+        systolic: readings.iter().map(|r| r.systolic).sum::<f64>() / n,
+        diastolic: readings.iter().map(|r| r.diastolic).sum::<f64>() / n,
+        heart_pulse: (!pulses.is_empty()).then(|| pulses.iter().sum::<f64>() / pulses.len() as f64),
+        readings: readings.len(),
+        start: readings.iter().map(|r| r.date).min()?,
+        end: readings.iter().map(|r| r.date).max()?,
+    })
+}
+
+/// Home monitoring protocol, e.g. ESH: two readings a minute apart morning
+/// and evening for a week, discarding the first day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HomeMonitoring {
+    /// Readings less than this apart are one session.
+    pub session_gap: Duration,
+    /// Drop readings of the first 24 hours after the first reading.
+    pub discard_first_day: bool,
+}
+
+impl Default for HomeMonitoring {
+    fn default() -> Self {
+        HomeMonitoring {
+            session_gap: Duration::from_secs(10 * 60),
+            discard_first_day: true,
+        }
+    }
+}
+
+impl HomeMonitoring {
+    /// Sessions of `readings`, each averaged, oldest first.
+    pub fn sessions(&self, readings: &[BloodPressureReading]) -> Vec<BloodPressureAverage> {
+        let mut readings = readings.iter().collect::<Vec<_>>();
+        readings.sort_by_key(|r| r.date);
+        if self.discard_first_day {
+            if let Some(first) = readings.first().map(|r| r.date) {
+                readings.retain(|r| r.date >= first + DAY);
+            }
+        }
+
+        let gap = self.session_gap.as_secs();
+        let mut sessions: Vec<Vec<BloodPressureReading>> = vec![];
+        for reading in readings {
+            match sessions.last_mut() {
+                Some(session) if reading.date - session.last().map_or(0, |r| r.date) < gap => {
+                    session.push(reading.clone())
+                }
+                _ => sessions.push(vec![reading.clone()]),
+            }
+        }
+        sessions.iter().filter_map(|s| average(s)).collect()
+    }
+
+    /// Mean of the session means, so that every session weighs the same
+    /// whatever its number of readings. `readings` counts every reading
+    /// used.
+    pub fn average(&self, readings: &[BloodPressureReading]) -> Option<BloodPressureAverage> {
+        let sessions = self.sessions(readings);
+        let n = sessions.len() as f64;
+        let pulses = sessions
+            .iter()
+            .filter_map(|s| s.heart_pulse)
+            .collect::<Vec<_>>();
+        Some(BloodPressureAverage {
+            systolic: sessions.iter().map(|s| s.systolic).sum::<f64>() / n,
+            diastolic: sessions.iter().map(|s| s.diastolic).sum::<f64>() / n,
+            heart_pulse: (!pulses.is_empty())
+                .then(|| pulses.iter().sum::<f64>() / pulses.len() as f64),
+            readings: sessions.iter().map(|s| s.readings).sum(),
+            start: sessions.first()?.start,
+            end: sessions.last()?.end,
+        })
+    }
+}
+
+fn aha_category(systolic: f64, diastolic: f64) -> AhaCategory {
+    if systolic > 180.0 || diastolic > 120.0 {
+        AhaCategory::Crisis
+    } else if systolic >= 140.0 || diastolic >= 90.0 {
+        AhaCategory::Stage2
+    } else if systolic >= 130.0 || diastolic >= 80.0 {
+        AhaCategory::Stage1
+    } else if systolic >= 120.0 {
+        AhaCategory::Elevated
+    } else {
+        AhaCategory::Normal
+    }
+}
+
+/// The higher of the grades of each pressure.
+fn esc_grade(systolic: f64, diastolic: f64) -> EscGrade {
+    let by_systolic = match systolic {
+        s if s >= 180.0 => EscGrade::Grade3,
+        s if s >= 160.0 => EscGrade::Grade2,
+        s if s >= 140.0 => EscGrade::Grade1,
+        s if s >= 130.0 => EscGrade::HighNormal,
+        s if s >= 120.0 => EscGrade::Normal,
+        _ => EscGrade::Optimal,
+    };
+    let by_diastolic = match diastolic {
+        d if d >= 110.0 => EscGrade::Grade3,
+        d if d >= 100.0 => EscGrade::Grade2,
+        d if d >= 90.0 => EscGrade::Grade1,
+        d if d >= 85.0 => EscGrade::HighNormal,
+        d if d >= 80.0 => EscGrade::Normal,
+        _ => EscGrade::Optimal,
+    };
+    by_systolic.max(by_diastolic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn reading(
+        date: u64,
+        systolic: f64,
+        diastolic: f64,
+        heart_pulse: Option<f64>,
+    ) -> BloodPressureReading {
+        BloodPressureReading {
+            grpid: date,
+            date,
+            systolic,
+            diastolic,
+            heart_pulse,
+            deviceid: None,
+        }
+    }
+
+    #[test]
+    fn test_from_groups() -> anyhow::Result<()> {
+        let groups: Vec<MeasureGroup> = serde_json::from_value(json!([
+            {
+                "grpid": 2, "attrib": 0, "date": 200, "created": 200, "category": 1,
+                "deviceid": "cc50f3",
+                "measures": [
+                    {"value": 128, "type": 10, "unit": 0},
+                    {"value": 84, "type": 9, "unit": 0},
+                    {"value": 67, "type": 11, "unit": 0}
+                ]
+            },
+            {
+                "grpid": 1, "attrib": 0, "date": 100, "created": 100, "category": 1,
+                "measures": [{"value": 1215, "type": 10, "unit": -1}, {"value": 79, "type": 9, "unit": 0}]
+            },
+            {
+                "grpid": 3, "attrib": 0, "date": 300, "created": 300, "category": 1,
+                "measures": [{"value": 80000, "type": 1, "unit": -3}, {"value": 70, "type": 11, "unit": 0}]
+            },
+            {
+                "grpid": 4, "attrib": 0, "date": 400, "created": 400, "category": 2,
+                "measures": [{"value": 120, "type": 10, "unit": 0}, {"value": 80, "type": 9, "unit": 0}]
+            }
+        ]))?;
+
+        assert_eq!(
+            BloodPressureReading::from_groups(&groups),
+            vec![
+                BloodPressureReading {
+                    grpid: 1,
+                    ..reading(100, 121.5, 79.0, None)
+                },
+                BloodPressureReading {
+                    grpid: 2,
+                    deviceid: Some("cc50f3".into()),
+                    ..reading(200, 128.0, 84.0, Some(67.0))
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(115.0, 75.0, AhaCategory::Normal, EscGrade::Optimal)]
+    #[case(125.0, 75.0, AhaCategory::Elevated, EscGrade::Normal)]
+    #[case(118.0, 82.0, AhaCategory::Stage1, EscGrade::Normal)]
+    #[case(135.0, 86.0, AhaCategory::Stage1, EscGrade::HighNormal)]
+    #[case(145.0, 85.0, AhaCategory::Stage2, EscGrade::Grade1)]
+    #[case(130.0, 105.0, AhaCategory::Stage2, EscGrade::Grade2)]
+    #[case(180.0, 100.0, AhaCategory::Stage2, EscGrade::Grade3)]
+    #[case(185.0, 95.0, AhaCategory::Crisis, EscGrade::Grade3)]
+    #[case(150.0, 125.0, AhaCategory::Crisis, EscGrade::Grade3)]
+    fn test_classification(
+        #[case] systolic: f64,
+        #[case] diastolic: f64,
+        #[case] aha: AhaCategory,
+        #[case] esc: EscGrade,
+    ) {
+        let reading = reading(0, systolic, diastolic, None);
+        assert_eq!(reading.aha_category(), aha);
+        assert_eq!(reading.esc_grade(), esc);
+    }
+
+    #[test]
+    fn test_average() {
+        assert_eq!(average(&[]), None);
+        assert_eq!(
+            average(&[
+                reading(100, 130.0, 80.0, Some(70.0)),
+                reading(160, 126.0, 84.0, None)
+            ]),
+            Some(BloodPressureAverage {
+                systolic: 128.0,
+                diastolic: 82.0,
+                heart_pulse: Some(70.0),
+                readings: 2,
+                start: 100,
+                end: 160,
+            })
+        );
+    }
+
+    #[test]
+    fn test_home_monitoring() {
+        let morning = 7 * 3600;
+        let evening = 19 * 3600;
+        let readings = vec![
+            // First day, discarded.
+            reading(morning, 170.0, 100.0, None),
+            reading(morning + 60, 168.0, 98.0, None),
+            // Two readings a minute apart, one session.
+            reading(DAY + morning, 140.0, 90.0, Some(70.0)),
+            reading(DAY + morning + 60, 136.0, 86.0, Some(66.0)),
+            // A single reading, weighing as much as the session above.
+            reading(DAY + evening, 130.0, 80.0, None),
+        ];
+
+        let protocol = HomeMonitoring::default();
+        let sessions = protocol.sessions(&readings);
+        assert_eq!(
+            sessions.iter().map(|s| s.readings).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+
+        let avg = protocol.average(&readings).unwrap();
+        assert_eq!((avg.systolic, avg.diastolic), (134.0, 84.0));
+        assert_eq!(avg.heart_pulse, Some(68.0));
+        assert_eq!(
+            (avg.readings, avg.start, avg.end),
+            (3, DAY + morning, DAY + evening)
+        );
+        assert_eq!(avg.aha_category(), AhaCategory::Stage1);
+
+        let all = HomeMonitoring {
+            discard_first_day: false,
+            ..Default::default()
+        };
+        assert_eq!(all.average(&readings).unwrap().readings, 5);
+        assert_eq!(protocol.average(&readings[..2]), None);
+    }
+}
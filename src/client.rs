@@ -0,0 +1,296 @@
+use std::time::Duration;
+
+use crate::api::cli::ApiCli;
+use crate::auth::cli::{AuthCli, Scope};
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::retry::RetryPolicy;
+use crate::{Error, Result, WITHINGS_ACCOUNT_URL, WITHINGS_API_URL};
+
+/// Entry point that shares one HTTP client and configuration between the
+/// OAuth API (`AuthCli`) and the data APIs (`ApiCli`).
+#[derive(Debug, Clone)]
+pub struct WithingsClient {
+    auth: AuthCli,
+}
+
+#[derive(Debug, Default)]
+pub struct WithingsClientBuilder {
+    client_id: Option<String>,
+    consumer_secret: Option<String>,
+    callback_uri: Option<String>,
+    scope: Vec<Scope>,
+    mode: Option<String>,
+    base_api_url: Option<String>,
+    account_url: Option<String>,
+    http_client: Option<reqwest::Client>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    proxy: Option<reqwest::Proxy>,
+    retry_policy: Option<RetryPolicy>,
+    rate_limit: Option<RateLimit>,
+    strict: bool,
+}
+
+impl WithingsClient {
+    pub fn builder() -> WithingsClientBuilder {
+        WithingsClientBuilder::default()
+    }
+
+    pub fn auth(&self) -> &AuthCli {
+        &self.auth
+    }
+
+    /// Data API client for the user owning `access_token`.
+    pub fn api(&self, access_token: impl Into<String>) -> ApiCli {
+        let api = ApiCli::with_client(
+            access_token.into(),
+            self.auth.base_api_url.clone(),
+            self.auth.client.clone(),
+        )
+        .with_retry_policy(self.auth.retry_policy.clone())
+        .with_strict(self.auth.strict);
+
+        match &self.auth.rate_limiter {
+            Some(limiter) => api.with_rate_limiter(limiter.clone()),
+            None => api,
+        }
+    }
+
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.auth.client
+    }
+}
+
+impl WithingsClientBuilder {
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = Some(client_id.into());
+        self
+    }
+
+    pub fn consumer_secret(mut self, consumer_secret: impl Into<String>) -> Self {
+        self.consumer_secret = Some(consumer_secret.into());
+        self
+    }
+
+    pub fn callback_uri(mut self, callback_uri: impl Into<String>) -> Self {
+        self.callback_uri = Some(callback_uri.into());
+        self
+    }
+
+    pub fn scope(mut self, scope: Vec<Scope>) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    pub fn mode(mut self, mode: impl Into<String>) -> Self {
+        self.mode = Some(mode.into());
+        self
+    }
+
+    pub fn base_api_url(mut self, base_api_url: impl Into<String>) -> Self {
+        self.base_api_url = Some(base_api_url.into());
+        self
+    }
+
+    pub fn account_url(mut self, account_url: impl Into<String>) -> Self {
+        self.account_url = Some(account_url.into());
+        self
+    }
+
+    /// Use a preconfigured `reqwest::Client`. When set, `timeout`,
+    /// `connect_timeout`, `user_agent` and `proxy` are ignored.
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Limit requests client-side. The limiter is shared by every client
+    /// built with the same `client_id`, so `build` fails with
+    /// `Error::Config` while one of them has a different limit.
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Fail on response fields this crate doesn't map instead of ignoring
+    /// them, e.g. in CI to detect API drift.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn build(self) -> Result<WithingsClient> {
+        let client_id = self
+            .client_id
+            .ok_or_else(|| Error::Config("client_id must be set".into()))?;
+        let consumer_secret = self
+            .consumer_secret
+            .ok_or_else(|| Error::Config("consumer_secret must be set".into()))?;
+        let callback_uri = self
+            .callback_uri
+            .ok_or_else(|| Error::Config("callback_uri must be set".into()))?;
+
+        let client = match self.http_client {
+            Some(client) => client,
+            None => {
+                let mut builder = reqwest::Client::builder();
+                if let Some(timeout) = self.timeout {
+                    builder = builder.timeout(timeout);
+                }
+                if let Some(timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                if let Some(user_agent) = self.user_agent {
+                    builder = builder.user_agent(user_agent);
+                }
+                if let Some(proxy) = self.proxy {
+                    builder = builder.proxy(proxy);
+                }
+                builder.build()?
+            }
+        };
+
+        let rate_limiter = self
+            .rate_limit
+            .map(|limit| RateLimiter::shared(&client_id, limit))
+            .transpose()?;
+
+        let auth = AuthCli {
+            client,
+            rate_limiter,
+            strict: self.strict,
+            ..AuthCli::new(
+                self.base_api_url.unwrap_or_else(|| WITHINGS_API_URL.into()),
+                client_id,
+                consumer_secret,
+                callback_uri,
+                self.scope,
+                self.mode,
+            )
+        }
+        .with_account_url(
+            self.account_url
+                .unwrap_or_else(|| WITHINGS_ACCOUNT_URL.into()),
+        )
+        .with_retry_policy(self.retry_policy.unwrap_or_else(RetryPolicy::none));
+
+        Ok(WithingsClient { auth })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::cli::GetMeasRequest;
+    use crate::auth::cli::State;
+    use serde_json::json;
+
+    fn builder() -> WithingsClientBuilder {
+        WithingsClient::builder()
+            .client_id("test_client_id")
+            .consumer_secret("test_consumer_secret")
+            .callback_uri("https://localhost")
+    }
+
+    #[test]
+    fn test_build_defaults() -> anyhow::Result<()> {
+        let client = builder().build()?;
+
+        assert_eq!(client.auth().base_api_url, WITHINGS_API_URL);
+        assert_eq!(client.auth().account_url, WITHINGS_ACCOUNT_URL);
+        assert_eq!(client.auth().client_id, "test_client_id");
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_requires_client_id() {
+        let res = WithingsClient::builder()
+            .consumer_secret("test_consumer_secret")
+            .callback_uri("https://localhost")
+            .build();
+
+        assert!(matches!(res, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_build_account_url() -> anyhow::Result<()> {
+        let client = builder()
+            .account_url("http://127.0.0.1:1234")
+            .scope(vec![Scope::UserMetrics])
+            .build()?;
+
+        assert!(client
+            .auth()
+            .get_authorize_url(&State::random())?
+            .starts_with("http://127.0.0.1:1234/oauth2_user/authorize2?"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_rate_limit() -> anyhow::Result<()> {
+        let limit = RateLimit {
+            requests_per_minute: 120,
+            per_user_requests_per_minute: Some(10),
+        };
+        let client = builder().rate_limit(limit.clone()).build()?;
+
+        assert_eq!(
+            client.auth().rate_limiter.as_ref().map(|l| l.limit()),
+            Some(&limit)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_api_shares_http_client() -> anyhow::Result<()> {
+        let client = builder()
+            .base_api_url(mockito::server_url())
+            .user_agent("withings-api-test")
+            .timeout(Duration::from_secs(5))
+            .build()?;
+
+        let mock = mockito::mock("POST", "/measure")
+            .match_header("user-agent", "withings-api-test")
+            .match_header("authorization", "Bearer access_token")
+            .with_status(200)
+            .with_body(serde_json::to_string(
+                &json!({"status": 0, "body": {"measuregrps": []}}),
+            )?)
+            .create();
+
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            ..Default::default()
+        };
+        client.api("access_token").get_meas(&req).await?;
+        mock.assert();
+
+        Ok(())
+    }
+}
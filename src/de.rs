@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString<T> {
+    Number(T),
+    String(String),
+}
+
+/// `deserialize_with` helper for ids and timestamps Withings sends either as
+/// numbers or as strings, e.g. `updatetime` or `userid`.
+pub fn number_or_string<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    match NumberOrString::<T>::deserialize(deserializer)? {
+        NumberOrString::Number(n) => Ok(n),
+        NumberOrString::String(s) => s.trim().parse().map_err(de::Error::custom),
+    }
+}
+
+/// [`number_or_string`] for optional fields; `null` and `""` are `None`.
+pub fn option_number_or_string<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    match Option::<NumberOrString<T>>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(NumberOrString::Number(n)) => Ok(Some(n)),
+        Some(NumberOrString::String(s)) if s.trim().is_empty() => Ok(None),
+        Some(NumberOrString::String(s)) => s.trim().parse().map(Some).map_err(de::Error::custom),
+    }
+}
+
+/// `deserialize_with` helper for flags sent as `true`, `1` or `"1"`, e.g.
+/// `more` of paged responses.
+pub fn bool_or_number<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrNumber {
+        Bool(bool),
+        Number(u64),
+        String(String),
+    }
+
+    match BoolOrNumber::deserialize(deserializer)? {
+        BoolOrNumber::Bool(b) => Ok(b),
+        BoolOrNumber::Number(n) => Ok(n != 0),
+        BoolOrNumber::String(s) => match s.trim() {
+            "true" | "1" => Ok(true),
+            "false" | "0" | "" => Ok(false),
+            s => Err(de::Error::custom(format!("invalid flag: {}", s))),
+        },
+    }
+}
+
+/// `deserialize_with` helper for series keyed by timestamp, which Withings
+/// sends as `[]` rather than `{}` when empty, e.g. intraday activity.
+pub fn map_or_empty_array<'de, D, K, V>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de> + Ord,
+    V: Deserialize<'de>,
+{
+    struct MapOrEmptyArray<K, V>(PhantomData<(K, V)>);
+
+    impl<'de, K, V> Visitor<'de> for MapOrEmptyArray<K, V>
+    where
+        K: Deserialize<'de> + Ord,
+        V: Deserialize<'de>,
+    {
+        type Value = BTreeMap<K, V>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map or an empty array")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut values = BTreeMap::new();
+            while let Some((k, v)) = map.next_entry()? {
+                values.insert(k, v);
+            }
+            Ok(values)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            match seq.next_element::<de::IgnoredAny>()? {
+                None => Ok(BTreeMap::new()),
+                Some(_) => Err(de::Error::invalid_length(1, &self)),
+            }
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(BTreeMap::new())
+        }
+    }
+
+    deserializer.deserialize_any(MapOrEmptyArray(PhantomData))
+}
+
+/// Enum of numeric Withings codes. Codes it doesn't list decode to
+/// `Unknown(code)` instead of failing, and encode back unchanged.
+macro_rules! code_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($variant:ident = $code:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant,)*
+            Unknown(u64),
+        }
+
+        impl $name {
+            pub fn code(&self) -> u64 {
+                match self {
+                    $($name::$variant => $code,)*
+                    $name::Unknown(code) => *code,
+                }
+            }
+        }
+
+        impl From<u64> for $name {
+            fn from(code: u64) -> $name {
+                match code {
+                    $($code => $name::$variant,)*
+                    code => $name::Unknown(code),
+                }
+            }
+        }
+
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_u64(self.code())
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<$name, D::Error> {
+                crate::de::number_or_string::<_, u64>(deserializer).map($name::from)
+            }
+        }
+
+        impl crate::params::ToParam for $name {
+            fn to_param(&self) -> String {
+                self.code().to_string()
+            }
+        }
+    };
+}
+
+pub(crate) use code_enum;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    code_enum! {
+        pub enum Color {
+            Red = 1,
+            Blue = 2,
+        }
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Body {
+        #[serde(deserialize_with = "number_or_string")]
+        id: u64,
+        #[serde(default, deserialize_with = "option_number_or_string")]
+        offset: Option<i64>,
+        #[serde(default)]
+        color: Option<Color>,
+        #[serde(default, deserialize_with = "bool_or_number")]
+        more: bool,
+    }
+
+    #[test]
+    fn test_number_or_string() -> anyhow::Result<()> {
+        for (value, expected) in [
+            (json!({"id": 363}), (363, None)),
+            (json!({"id": "363", "offset": "-2"}), (363, Some(-2))),
+            (json!({"id": " 363 ", "offset": -2}), (363, Some(-2))),
+            (json!({"id": 363, "offset": ""}), (363, None)),
+            (json!({"id": 363, "offset": null}), (363, None)),
+        ] {
+            let body: Body = serde_json::from_value(value)?;
+            assert_eq!((body.id, body.offset), expected);
+        }
+
+        assert!(serde_json::from_value::<Body>(json!({"id": "abc"})).is_err());
+        assert!(serde_json::from_value::<Body>(json!({"id": -1})).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bool_or_number() -> anyhow::Result<()> {
+        for (value, expected) in [
+            (json!(true), true),
+            (json!(0), false),
+            (json!(1), true),
+            (json!("1"), true),
+            (json!("false"), false),
+        ] {
+            let body: Body = serde_json::from_value(json!({"id": 1, "more": value}))?;
+            assert_eq!(body.more, expected);
+        }
+        assert!(serde_json::from_value::<Body>(json!({"id": 1, "more": "maybe"})).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_or_empty_array() -> anyhow::Result<()> {
+        #[derive(Debug, Deserialize)]
+        struct Series {
+            #[serde(deserialize_with = "map_or_empty_array")]
+            series: BTreeMap<u64, u64>,
+        }
+
+        let body: Series = serde_json::from_value(json!({"series": {"20": 2, "10": 1}}))?;
+        assert_eq!(
+            body.series.into_iter().collect::<Vec<_>>(),
+            vec![(10, 1), (20, 2)]
+        );
+        let body: Series = serde_json::from_value(json!({"series": []}))?;
+        assert!(body.series.is_empty());
+        assert!(serde_json::from_value::<Series>(json!({"series": [1]})).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_code_enum() -> anyhow::Result<()> {
+        for (value, expected) in [
+            (json!(2), Color::Blue),
+            (json!("1"), Color::Red),
+            (json!(99), Color::Unknown(99)),
+        ] {
+            let body: Body = serde_json::from_value(json!({"id": 1, "color": value}))?;
+            assert_eq!(body.color, Some(expected));
+        }
+
+        assert_eq!(serde_json::to_value(Color::Unknown(99))?, json!(99));
+        assert_eq!(Color::from(1).code(), 1);
+        Ok(())
+    }
+}
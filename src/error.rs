@@ -1,91 +1,390 @@
-use std::fmt::Debug;
+use std::fmt;
 
 use reqwest::Response;
 use reqwest::StatusCode;
 use serde::de::DeserializeOwned;
 
+use crate::response::{decode, decode_strict};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Transport(#[from] reqwest::Error),
+    #[error(transparent)]
+    Decode(#[from] serde_json::Error),
+    #[error(transparent)]
+    Encode(#[from] serde_urlencoded::ser::Error),
+    #[error(transparent)]
+    Url(#[from] url::ParseError),
+    #[error(transparent)]
+    Api(#[from] WithingsApiError),
+    #[error("Status: {status} Req: {request} Res: {response:#?}")]
+    HttpStatus {
+        status: StatusCode,
+        request: RequestMeta,
+        response: String,
+    },
+    #[error(transparent)]
+    Authorization(#[from] AuthorizationError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    /// Fields of a response that no field of the target type maps, reported
+    /// in strict mode.
+    #[error("Unknown fields in response of {request}: {fields:?}")]
+    UnknownFields {
+        request: RequestMeta,
+        fields: Vec<String>,
+    },
+    /// Failure of a cursor store or sink used by `sync`.
+    #[error("Storage error: {0}")]
+    Storage(Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[cfg(feature = "arrow")]
+impl From<arrow::error::ArrowError> for Error {
+    fn from(e: arrow::error::ArrowError) -> Self {
+        Error::Storage(Box::new(e))
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(e: parquet::errors::ParquetError) -> Self {
+        Error::Storage(Box::new(e))
+    }
+}
+
+#[cfg(feature = "csv")]
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Io(e.into())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Storage(Box::new(e))
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum WithingsApiError {
-    #[error("Status: {status} Req: {request:#?} Res: {response:#?}")]
+    #[error("Status: {status} Error: {error:?} Req: {request} Res: {response:#?}")]
+    Temporary {
+        status: u64,
+        error: Option<String>,
+        request: RequestMeta,
+        response: String,
+    },
+    #[error("Status: {status} Error: {error:?} Req: {request} Res: {response:#?}")]
     AuthenticationFailed {
         status: u64,
-        request: String,
+        error: Option<String>,
+        request: RequestMeta,
         response: String,
     },
-    #[error("Status: {status} Req: {request:#?} Res: {response:#?}")]
+    #[error("Status: {status} Error: {error:?} Req: {request} Res: {response:#?}")]
     InvalidParams {
         status: u64,
-        request: String,
+        error: Option<String>,
+        request: RequestMeta,
         response: String,
     },
-    #[error("Status: {status} Req: {request:#?} Res: {response:#?}")]
-    NotImplemented {
+    #[error("Status: {status} Error: {error:?} Req: {request} Res: {response:#?}")]
+    UnAuthorized {
         status: u64,
-        request: String,
+        error: Option<String>,
+        request: RequestMeta,
         response: String,
     },
-    #[error("Status: {status} Req: {request:#?} Res: {response:#?}")]
-    UnAuthorized {
+    #[error("Status: {status} Error: {error:?} Req: {request} Res: {response:#?}")]
+    TooManyRequests {
         status: u64,
-        request: String,
+        error: Option<String>,
+        request: RequestMeta,
         response: String,
     },
-    #[error("Status: {status} Req: {request:#?} Res: {response:#?}")]
+    #[error("Status: {status} Error: {error:?} Req: {request} Res: {response:#?}")]
+    NotImplemented {
+        status: u64,
+        error: Option<String>,
+        request: RequestMeta,
+        response: String,
+    },
+    #[error("Status: {status} Error: {error:?} Req: {request} Res: {response:#?}")]
     AnErrorOccurred {
         status: u64,
-        request: String,
+        error: Option<String>,
+        request: RequestMeta,
         response: String,
     },
-    #[error("Status: {status} Req: {request:#?} Res: {response:#?}")]
-    InternalServerError {
-        status: StatusCode,
-        request: String,
+    #[error("Status: {status} Error: {error:?} Req: {request} Res: {response:#?}")]
+    UnknownStatus {
+        status: u64,
+        error: Option<String>,
+        request: RequestMeta,
+        response: String,
+    },
+}
+
+impl Error {
+    /// Whether the request may succeed when sent again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Transport(e) => e.is_timeout() || e.is_connect(),
+            Error::Api(e) => e.is_retryable(),
+            Error::HttpStatus { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            Error::Decode(_)
+            | Error::Encode(_)
+            | Error::Url(_)
+            | Error::Authorization(_)
+            | Error::Io(_)
+            | Error::Config(_)
+            | Error::UnknownFields { .. }
+            | Error::Storage(_) => false,
+        }
+    }
+}
+
+impl WithingsApiError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            WithingsApiError::Temporary { .. } | WithingsApiError::TooManyRequests { .. }
+        )
+    }
+
+    /// Map a non-zero body status to its error.
+    /// https://developer.withings.com/api-reference#section/Response-status
+    pub fn from_body_status(
+        status: u64,
+        error: Option<String>,
+        request: RequestMeta,
         response: String,
+    ) -> WithingsApiError {
+        match status {
+            1..=3 => WithingsApiError::Temporary {
+                status,
+                error,
+                request,
+                response,
+            },
+            100..=102 | 200..=216 => WithingsApiError::AuthenticationFailed {
+                status,
+                error,
+                request,
+                response,
+            },
+            217..=295 | 300..=399 | 501..=511 => WithingsApiError::InvalidParams {
+                status,
+                error,
+                request,
+                response,
+            },
+            401 => WithingsApiError::UnAuthorized {
+                status,
+                error,
+                request,
+                response,
+            },
+            601 => WithingsApiError::TooManyRequests {
+                status,
+                error,
+                request,
+                response,
+            },
+            2554 => WithingsApiError::NotImplemented {
+                status,
+                error,
+                request,
+                response,
+            },
+            2555 => WithingsApiError::AnErrorOccurred {
+                status,
+                error,
+                request,
+                response,
+            },
+            _ => WithingsApiError::UnknownStatus {
+                status,
+                error,
+                request,
+                response,
+            },
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthorizationError {
+    #[error("OAuth state returned on the callback doesn't match the one sent")]
+    StateMismatch,
+    #[error("Authorization denied: {error} {description:?}")]
+    AuthorizationDenied {
+        error: String,
+        description: Option<String>,
     },
+    #[error("Authorization code is missing from the callback")]
+    MissingCode,
+    #[error("Callback URI must be an http localhost URL for loopback login: {0}")]
+    UnsupportedCallbackUri(String),
+    #[error("No authorization callback was received before the login deadline")]
+    LoginTimedOut,
 }
 
-pub async fn handle_response<T: Debug, U: DeserializeOwned>(
-    req: T,
+/// Non-sensitive description of a request, carried by errors in place of the
+/// request itself so that secrets and tokens never reach logs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestMeta {
+    pub url: String,
+    pub action: String,
+}
+
+impl RequestMeta {
+    pub fn new(url: &str, action: &str) -> RequestMeta {
+        RequestMeta {
+            url: url.into(),
+            action: action.into(),
+        }
+    }
+}
+
+impl fmt::Display for RequestMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} action={}", self.url, self.action)
+    }
+}
+
+pub async fn handle_response<U: DeserializeOwned>(req: RequestMeta, res: Response) -> Result<U> {
+    handle_response_with(req, res, false).await
+}
+
+pub(crate) async fn handle_response_with<U: DeserializeOwned>(
+    req: RequestMeta,
     res: Response,
-) -> anyhow::Result<U> {
+    strict: bool,
+) -> Result<U> {
     if !res.status().is_success() {
-        return Err(WithingsApiError::InternalServerError {
+        return Err(Error::HttpStatus {
             status: res.status(),
-            request: format!("{:#?}", req),
-            response: format!("{:#?}", res.text().await?),
+            request: req,
+            response: res.text().await?,
+        });
+    }
+
+    let bytes = res.bytes().await?;
+    if strict {
+        decode_strict(req, &bytes)
+    } else {
+        decode(req, &bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use rstest::rstest;
+
+    fn from_body_status(status: u64) -> WithingsApiError {
+        WithingsApiError::from_body_status(
+            status,
+            Some("test error".into()),
+            RequestMeta::new("https://wbsapi.withings.net/measure", "getmeas"),
+            "response".into(),
+        )
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(3)]
+    fn test_temporary(#[case] status: u64) {
+        assert_matches!(from_body_status(status), WithingsApiError::Temporary { status: s, error, .. } => {
+            assert_eq!(s, status);
+            assert_eq!(error.as_deref(), Some("test error"));
+        });
+    }
+
+    #[rstest]
+    #[case(100)]
+    #[case(102)]
+    #[case(200)]
+    #[case(201)]
+    #[case(216)]
+    fn test_authentication_failed(#[case] status: u64) {
+        assert_matches!(from_body_status(status), WithingsApiError::AuthenticationFailed { status: s, .. } => {
+            assert_eq!(s, status);
+        });
+    }
+
+    #[rstest]
+    #[case(217)]
+    #[case(295)]
+    #[case(300)]
+    #[case(399)]
+    #[case(501)]
+    #[case(511)]
+    fn test_invalid_params(#[case] status: u64) {
+        assert_matches!(from_body_status(status), WithingsApiError::InvalidParams { status: s, .. } => {
+            assert_eq!(s, status);
+        });
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(from_body_status(1).is_retryable());
+        assert!(from_body_status(601).is_retryable());
+        assert!(!from_body_status(100).is_retryable());
+        assert!(!from_body_status(503).is_retryable());
+        assert!(Error::Api(from_body_status(601)).is_retryable());
+        assert!(Error::HttpStatus {
+            status: StatusCode::BAD_GATEWAY,
+            request: RequestMeta::new("https://wbsapi.withings.net/measure", "getmeas"),
+            response: "".into(),
         }
-        .into());
-    }
-
-    let value: serde_json::Value = res.json().await?;
-    return if let Some(body_status) = value["status"].as_u64() {
-        // https://developer.withings.com/api-reference#section/Response-status
-        match body_status {
-            0 => Ok(serde_json::from_value(value)?),
-            100..=102 | 200 => Err(WithingsApiError::AuthenticationFailed {
-                status: body_status,
-                request: format!("{:#?}", req),
-                response: format!("{:#?}", value),
-            }
-            .into()),
-            501..=511 => Err(WithingsApiError::InvalidParams {
-                status: body_status,
-                request: format!("{:#?}", req),
-                response: format!("{:#?}", value),
-            }
-            .into()),
-            _ => Err(anyhow::anyhow!(
-                "Something else happened. Status: {:?} Req: {:#?} Res: {:#?}",
-                body_status,
-                req,
-                value
-            )),
+        .is_retryable());
+        assert!(!Error::HttpStatus {
+            status: StatusCode::BAD_REQUEST,
+            request: RequestMeta::new("https://wbsapi.withings.net/measure", "getmeas"),
+            response: "".into(),
         }
-    } else {
-        Err(anyhow::anyhow!(
-            "Body status couldn't be parsed. Req: {:#?} Res: {:#?}",
-            req,
-            value
-        ))
-    };
+        .is_retryable());
+        assert!(!Error::Config("".into()).is_retryable());
+    }
+
+    #[test]
+    fn test_single_statuses() {
+        assert_matches!(from_body_status(401), WithingsApiError::UnAuthorized { .. });
+        assert_matches!(
+            from_body_status(601),
+            WithingsApiError::TooManyRequests { .. }
+        );
+        assert_matches!(
+            from_body_status(2554),
+            WithingsApiError::NotImplemented { .. }
+        );
+        assert_matches!(
+            from_body_status(2555),
+            WithingsApiError::AnErrorOccurred { .. }
+        );
+    }
+
+    #[rstest]
+    #[case(4)]
+    #[case(296)]
+    #[case(400)]
+    #[case(512)]
+    #[case(9999)]
+    fn test_unknown_status(#[case] status: u64) {
+        assert_matches!(from_body_status(status), WithingsApiError::UnknownStatus { status: s, .. } => {
+            assert_eq!(s, status);
+        });
+    }
 }
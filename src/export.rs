@@ -0,0 +1,438 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use chrono_tz::Tz;
+
+use crate::api::activity::Activity;
+use crate::api::measure::{MeasureBody, MeasureType};
+use crate::api::sleep::SleepSummary;
+use crate::time::{from_timestamp, parse_timezone};
+use crate::Result;
+
+/// `Wide` writes one row per record and one column per measure type or
+/// field; `Long` writes one row per value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    #[default]
+    Wide,
+    Long,
+}
+
+/// Writes responses to CSV with real-valued measures and ISO 8601
+/// timestamps in the user's timezone.
+#[derive(Debug, Clone, Default)]
+pub struct CsvExport {
+    layout: Layout,
+    timezone: Option<Tz>,
+}
+
+impl CsvExport {
+    pub fn new(layout: Layout) -> CsvExport {
+        CsvExport {
+            layout,
+            timezone: None,
+        }
+    }
+
+    /// Timezone of the timestamps, instead of the one of each response or
+    /// record. UTC is used when neither is known.
+    pub fn with_timezone(mut self, timezone: Tz) -> CsvExport {
+        self.timezone = Some(timezone);
+        self
+    }
+
+    pub fn write_measures<W: io::Write>(&self, w: W, body: &MeasureBody) -> Result<()> {
+        let tz = self.tz(body.timezone.as_deref());
+        let mut w = csv::Writer::from_writer(w);
+        match self.layout {
+            Layout::Wide => {
+                // Most measures of each type in one group, e.g. one
+                // segmental measure per body part.
+                let mut types = BTreeMap::<u64, usize>::new();
+                for grp in &body.measuregrps {
+                    let mut counts = BTreeMap::<u64, usize>::new();
+                    for m in &grp.measures {
+                        *counts.entry(m.meastype.code()).or_default() += 1;
+                    }
+                    for (code, count) in counts {
+                        let max = types.entry(code).or_default();
+                        *max = (*max).max(count);
+                    }
+                }
+                let mut header = vec!["grpid".to_string(), "date".into(), "category".into()];
+                for (&code, &count) in &types {
+                    let meastype = MeasureType::from(code);
+                    if count == 1 {
+                        header.push(column(meastype, None));
+                    } else {
+                        header.extend((0..count).map(|i| column(meastype, Some(i))));
+                    }
+                }
+                w.write_record(&header)?;
+
+                for grp in &body.measuregrps {
+                    let mut row = vec![
+                        grp.grpid.to_string(),
+                        iso(grp.date, &tz),
+                        grp.category.code().to_string(),
+                    ];
+                    for (&code, &count) in &types {
+                        let mut values = grp
+                            .measures
+                            .iter()
+                            .filter(|m| m.meastype.code() == code)
+                            .map(|m| m.real_value().to_string());
+                        row.extend((0..count).map(|_| values.next().unwrap_or_default()));
+                    }
+                    w.write_record(&row)?;
+                }
+            }
+            Layout::Long => {
+                w.write_record(["grpid", "date", "category", "type", "unit", "value"])?;
+                for grp in &body.measuregrps {
+                    for m in &grp.measures {
+                        w.write_record([
+                            grp.grpid.to_string(),
+                            iso(grp.date, &tz),
+                            grp.category.code().to_string(),
+                            type_name(m.meastype),
+                            m.meastype.unit().unwrap_or_default().to_string(),
+                            m.real_value().to_string(),
+                        ])?;
+                    }
+                }
+            }
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Activity is per day, so `date` is the local day rather than a
+    /// timestamp.
+    pub fn write_activities<W: io::Write>(&self, w: W, activities: &[Activity]) -> Result<()> {
+        let rows = activities.iter().map(|a| {
+            let key = vec![a.date.to_string()];
+            (key, activity_fields(a))
+        });
+        self.write_fields(w, &["date"], ACTIVITY_FIELDS, rows)
+    }
+
+    pub fn write_sleep_summaries<W: io::Write>(
+        &self,
+        w: W,
+        summaries: &[SleepSummary],
+    ) -> Result<()> {
+        let rows = summaries.iter().map(|s| {
+            let tz = self.tz(s.timezone.as_deref());
+            let key = vec![
+                s.id.to_string(),
+                s.date.to_string(),
+                iso(s.startdate, &tz),
+                iso(s.enddate, &tz),
+            ];
+            (key, sleep_fields(s))
+        });
+        self.write_fields(
+            w,
+            &["id", "date", "startdate", "enddate"],
+            SLEEP_FIELDS,
+            rows,
+        )
+    }
+
+    /// Wide: `keys` then one column per field. Long: `keys`, `field` and
+    /// `value`, skipping missing values.
+    fn write_fields<W: io::Write>(
+        &self,
+        w: W,
+        keys: &[&str],
+        fields: &[&str],
+        rows: impl Iterator<Item = (Vec<String>, Vec<Option<f64>>)>,
+    ) -> Result<()> {
+        let mut w = csv::Writer::from_writer(w);
+        match self.layout {
+            Layout::Wide => {
+                w.write_record(keys.iter().chain(fields))?;
+                for (key, values) in rows {
+                    let values = values
+                        .iter()
+                        .map(|v| v.map(|v| v.to_string()).unwrap_or_default());
+                    w.write_record(key.into_iter().chain(values))?;
+                }
+            }
+            Layout::Long => {
+                w.write_record(keys.iter().chain(&["field", "value"]))?;
+                for (key, values) in rows {
+                    for (field, value) in fields.iter().zip(values) {
+                        if let Some(value) = value {
+                            let row = key
+                                .iter()
+                                .cloned()
+                                .chain([field.to_string(), value.to_string()]);
+                            w.write_record(row)?;
+                        }
+                    }
+                }
+            }
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    fn tz(&self, record_timezone: Option<&str>) -> Tz {
+        self.timezone
+            .or_else(|| record_timezone.and_then(parse_timezone))
+            .unwrap_or(Tz::UTC)
+    }
+}
+
+/// Measure type name, `type_<code>` for unknown types.
+fn type_name(meastype: MeasureType) -> String {
+    meastype
+        .name()
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("type_{}", meastype.code()))
+}
+
+/// e.g. `weight (kg)`, or `fat_mass_segments[1] (kg)` for the second
+/// measure of a type repeated in a group.
+fn column(meastype: MeasureType, position: Option<usize>) -> String {
+    let mut column = type_name(meastype);
+    if let Some(position) = position {
+        column = format!("{}[{}]", column, position);
+    }
+    match meastype.unit() {
+        Some(unit) => format!("{} ({})", column, unit),
+        None => column,
+    }
+}
+
+fn iso(secs: u64, tz: &Tz) -> String {
+    from_timestamp(secs, tz)
+        .map(|at| at.to_rfc3339())
+        .unwrap_or_default()
+}
+
+const ACTIVITY_FIELDS: &[&str] = &[
+    "steps",
+    "distance",
+    "elevation",
+    "soft",
+    "moderate",
+    "intense",
+    "active",
+    "calories",
+    "totalcalories",
+    "hr_average",
+    "hr_min",
+    "hr_max",
+];
+
+fn activity_fields(a: &Activity) -> Vec<Option<f64>> {
+    let n = |v: Option<u64>| v.map(|v| v as f64);
+    vec![
+        n(a.steps),
+        a.distance,
+        a.elevation,
+        n(a.soft),
+        n(a.moderate),
+        n(a.intense),
+        n(a.active),
+        a.calories,
+        a.totalcalories,
+        n(a.hr_average),
+        n(a.hr_min),
+        n(a.hr_max),
+    ]
+}
+
+const SLEEP_FIELDS: &[&str] = &[
+    "total_timeinbed",
+    "total_sleep_time",
+    "lightsleepduration",
+    "deepsleepduration",
+    "remsleepduration",
+    "durationtosleep",
+    "durationtowakeup",
+    "wakeupcount",
+    "wakeupduration",
+    "sleep_efficiency",
+    "sleep_score",
+    "hr_average",
+    "hr_min",
+    "hr_max",
+    "rr_average",
+    "rr_min",
+    "rr_max",
+    "snoring",
+    "snoringepisodecount",
+    "breathing_disturbances_intensity",
+    "apnea_hypopnea_index",
+];
+
+fn sleep_fields(s: &SleepSummary) -> Vec<Option<f64>> {
+    let n = |v: Option<u64>| v.map(|v| v as f64);
+    let d = &s.data;
+    vec![
+        n(d.total_timeinbed),
+        n(d.total_sleep_time),
+        n(d.lightsleepduration),
+        n(d.deepsleepduration),
+        n(d.remsleepduration),
+        n(d.durationtosleep),
+        n(d.durationtowakeup),
+        n(d.wakeupcount),
+        n(d.wakeupduration),
+        d.sleep_efficiency,
+        n(d.sleep_score),
+        n(d.hr_average),
+        n(d.hr_min),
+        n(d.hr_max),
+        n(d.rr_average),
+        n(d.rr_min),
+        n(d.rr_max),
+        n(d.snoring),
+        n(d.snoringepisodecount),
+        n(d.breathing_disturbances_intensity),
+        d.apnea_hypopnea_index,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sleep::SleepSummaryBody;
+    use crate::test_util::{group, measure_body, measure_group, weigh_in};
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn measures() -> MeasureBody {
+        measure_body(
+            Some("Asia/Tokyo"),
+            vec![
+                weigh_in(),
+                measure_group(
+                    2,
+                    1644056071,
+                    json!([{"value": 120, "type": 10, "unit": 0}, {"value": 215, "type": 6, "unit": -1}]),
+                ),
+            ],
+        )
+    }
+
+    fn export(export: CsvExport, f: impl FnOnce(&CsvExport, &mut Vec<u8>) -> Result<()>) -> String {
+        let mut out = vec![];
+        f(&export, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_measures_wide() {
+        let csv = export(CsvExport::new(Layout::Wide), |e, w| {
+            e.write_measures(w, &measures())
+        });
+        assert_eq!(
+            csv,
+            "grpid,date,category,weight (kg),fat_ratio (%),systolic_blood_pressure (mm[Hg]),type_999\n\
+             1,2022-02-04T19:14:31+09:00,1,80.125,,,42\n\
+             2,2022-02-05T19:14:31+09:00,1,,21.5,120,\n"
+        );
+
+        // One column per segment.
+        let segments = measure_body(
+            None,
+            vec![
+                measure_group(
+                    3,
+                    1644056071,
+                    json!([
+                        {"value": 1040, "type": 173, "unit": -2},
+                        {"value": 1020, "type": 173, "unit": -2}
+                    ]),
+                ),
+                group(4, 1644142471),
+            ],
+        );
+        let csv = export(CsvExport::new(Layout::Wide), |e, w| {
+            e.write_measures(w, &segments)
+        });
+        assert_eq!(
+            csv,
+            "grpid,date,category,weight (kg),fat_free_mass_segments[0] (kg),fat_free_mass_segments[1] (kg)\n\
+             3,2022-02-05T10:14:31+00:00,1,,10.4,10.2\n\
+             4,2022-02-06T10:14:31+00:00,1,80,,\n"
+        );
+    }
+
+    #[test]
+    fn test_write_measures_long() {
+        let csv = export(
+            CsvExport::new(Layout::Long).with_timezone(Tz::UTC),
+            |e, w| e.write_measures(w, &measures()),
+        );
+        assert_eq!(
+            csv,
+            "grpid,date,category,type,unit,value\n\
+             1,2022-02-04T10:14:31+00:00,1,weight,kg,80.125\n\
+             1,2022-02-04T10:14:31+00:00,1,type_999,,42\n\
+             2,2022-02-05T10:14:31+00:00,1,systolic_blood_pressure,mm[Hg],120\n\
+             2,2022-02-05T10:14:31+00:00,1,fat_ratio,%,21.5\n"
+        );
+    }
+
+    #[test]
+    fn test_write_activities_and_sleep() -> anyhow::Result<()> {
+        let activities: Vec<Activity> = serde_json::from_value(json!([
+            {"date": "2022-02-04", "steps": 8123, "distance": 6012.5}
+        ]))?;
+        let csv = export(CsvExport::new(Layout::Long), |e, w| {
+            e.write_activities(w, &activities)
+        });
+        assert_eq!(
+            csv,
+            "date,field,value\n2022-02-04,steps,8123\n2022-02-04,distance,6012.5\n"
+        );
+
+        let sleep: SleepSummaryBody = serde_json::from_value(json!({
+            "series": [{
+                "id": 2081804,
+                "timezone": "Europe/Paris",
+                "startdate": 1643899800,
+                "enddate": 1643926500,
+                "date": "2022-02-04",
+                "created": 1643927120,
+                "modified": 1643927130,
+                "data": {"sleep_score": 81, "sleep_efficiency": 0.92}
+            }]
+        }))?;
+        let csv = export(CsvExport::new(Layout::Wide), |e, w| {
+            e.write_sleep_summaries(w, &sleep.series)
+        });
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines
+                .next()
+                .map(|header| header.split(',').take(5).collect::<Vec<_>>()),
+            Some(vec![
+                "id",
+                "date",
+                "startdate",
+                "enddate",
+                "total_timeinbed"
+            ])
+        );
+        let row = lines.next().unwrap().split(',').collect::<Vec<_>>();
+        assert_eq!(
+            row[..4],
+            [
+                "2081804",
+                "2022-02-04",
+                "2022-02-03T15:50:00+01:00",
+                "2022-02-03T23:15:00+01:00"
+            ]
+        );
+        assert_eq!(row[4 + 9], "0.92");
+        assert_eq!(row[4 + 10], "81");
+        Ok(())
+    }
+}
@@ -0,0 +1,435 @@
+use chrono::SecondsFormat;
+use serde::Serialize;
+
+use crate::api::measure::{Category, Measure, MeasureGroup, MeasureType};
+use crate::time::from_timestamp;
+
+const LOINC: &str = "http://loinc.org";
+const UCUM: &str = "http://unitsofmeasure.org";
+const OBSERVATION_CATEGORY: &str = "http://terminology.hl7.org/CodeSystem/observation-category";
+const WITHINGS_MEASURE: &str = "https://wbsapi.withings.net/measure";
+
+/// FHIR R4 `Bundle` of type `collection`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bundle {
+    pub resource_type: &'static str,
+    #[serde(rename = "type")]
+    pub bundle_type: &'static str,
+    pub entry: Vec<BundleEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BundleEntry {
+    pub resource: Observation,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Observation {
+    pub resource_type: &'static str,
+    pub identifier: Vec<Identifier>,
+    pub status: &'static str,
+    pub category: Vec<CodeableConcept>,
+    pub code: CodeableConcept,
+    pub subject: Reference,
+    pub effective_date_time: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_quantity: Option<Quantity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<Reference>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub component: Vec<Component>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Component {
+    pub code: CodeableConcept,
+    pub value_quantity: Quantity,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CodeableConcept {
+    pub coding: Vec<Coding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Coding {
+    pub system: &'static str,
+    pub code: &'static str,
+    pub display: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: &'static str,
+    pub system: &'static str,
+    pub code: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Reference {
+    pub reference: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Identifier {
+    pub system: &'static str,
+    pub value: String,
+}
+
+/// LOINC code, display and observation category of the measure types that
+/// have one. Other types are not mapped.
+pub fn loinc(meastype: MeasureType) -> Option<(&'static str, &'static str, &'static str)> {
+    use MeasureType::*;
+    Some(match meastype {
+        Weight => ("29463-7", "Body weight", "vital-signs"),
+        Height => ("8302-2", "Body height", "vital-signs"),
+        FatRatio => ("41982-0", "Percentage of body fat Measured", "exam"),
+        SystolicBloodPressure => ("8480-6", "Systolic blood pressure", "vital-signs"),
+        DiastolicBloodPressure => ("8462-4", "Diastolic blood pressure", "vital-signs"),
+        HeartPulse => ("8867-4", "Heart rate", "vital-signs"),
+        Spo2 => (
+            "2708-6",
+            "Oxygen saturation in Arterial blood",
+            "vital-signs",
+        ),
+        Temperature | BodyTemperature => ("8310-5", "Body temperature", "vital-signs"),
+        SkinTemperature => ("39106-0", "Temperature of Skin", "vital-signs"),
+        _ => return None,
+    })
+}
+
+/// Map real measure groups to Observations of `patient`, e.g.
+/// `Patient/123`, following the R4 vital signs profiles. Systolic and
+/// diastolic pressures of a group become one blood pressure panel (85354-9)
+/// with two components; a pressure measured alone is its own Observation.
+/// Objectives, measure types without a LOINC code and groups whose date
+/// can't be represented are skipped.
+pub fn bundle(patient: &str, groups: &[MeasureGroup]) -> Bundle {
+    let entry = groups
+        .iter()
+        .filter(|grp| grp.category == Category::Real)
+        .flat_map(|grp| observations(patient, grp))
+        .map(|resource| BundleEntry { resource })
+        .collect();
+    Bundle {
+        resource_type: "Bundle",
+        bundle_type: "collection",
+        entry,
+    }
+}
+
+fn observations(patient: &str, grp: &MeasureGroup) -> Vec<Observation> {
+    let Some(effective) = from_timestamp(grp.date, &chrono::Utc) else {
+        return vec![];
+    };
+    let effective = effective.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let observation = |suffix: &str, code, category| Observation {
+        resource_type: "Observation",
+        identifier: vec![Identifier {
+            system: WITHINGS_MEASURE,
+            value: format!("{}-{}", grp.grpid, suffix),
+        }],
+        status: "final",
+        category: vec![CodeableConcept {
+            coding: vec![Coding {
+                system: OBSERVATION_CATEGORY,
+                code: category,
+                display: match category {
+                    "vital-signs" => "Vital Signs",
+                    _ => "Exam",
+                },
+            }],
+            text: None,
+        }],
+        code,
+        subject: Reference {
+            reference: patient.to_string(),
+        },
+        effective_date_time: effective.clone(),
+        value_quantity: None,
+        device: grp.deviceid.as_ref().map(|deviceid| Reference {
+            reference: format!("Device/{}", deviceid),
+        }),
+        component: vec![],
+    };
+
+    // The panel profile requires both pressures.
+    let find = |t| grp.measures.iter().find(|m| m.meastype == t);
+    let panel = match (
+        find(MeasureType::SystolicBloodPressure),
+        find(MeasureType::DiastolicBloodPressure),
+    ) {
+        (Some(systolic), Some(diastolic)) => Some([systolic, diastolic]),
+        _ => None,
+    };
+
+    let mut observations = vec![];
+    if let Some(pressures) = panel {
+        let mut panel = observation(
+            "bp",
+            coding("85354-9", "Blood pressure panel with all children optional"),
+            "vital-signs",
+        );
+        panel.component = pressures
+            .iter()
+            .filter_map(|m| {
+                let (code, display, _) = loinc(m.meastype)?;
+                Some(Component {
+                    code: coding(code, display),
+                    value_quantity: quantity(m)?,
+                })
+            })
+            .collect();
+        observations.push(panel);
+    }
+
+    for m in &grp.measures {
+        if panel.is_some() && is_pressure(m) {
+            continue;
+        }
+        let Some((code, display, category)) = loinc(m.meastype) else {
+            continue;
+        };
+        let mut code = coding(code, display);
+        if m.meastype == MeasureType::Spo2 {
+            code.coding.push(Coding {
+                system: LOINC,
+                code: "59408-5",
+                display: "Oxygen saturation in Arterial blood by Pulse oximetry",
+            });
+        }
+        let mut obs = observation(&m.meastype.code().to_string(), code, category);
+        obs.value_quantity = quantity(m);
+        observations.push(obs);
+    }
+    observations
+}
+
+fn is_pressure(m: &Measure) -> bool {
+    matches!(
+        m.meastype,
+        MeasureType::SystolicBloodPressure | MeasureType::DiastolicBloodPressure
+    )
+}
+
+fn coding(code: &'static str, display: &'static str) -> CodeableConcept {
+    CodeableConcept {
+        coding: vec![Coding {
+            system: LOINC,
+            code,
+            display,
+        }],
+        text: Some(display.to_string()),
+    }
+}
+
+/// The body height profile only allows `cm` and `[in_i]`.
+fn quantity(m: &Measure) -> Option<Quantity> {
+    let (value, unit) = match m.meastype {
+        MeasureType::Height => {
+            let exp = m.unit + 2;
+            let cm = if exp < 0 {
+                m.value as f64 / 10f64.powi(-exp as i32)
+            } else {
+                m.value as f64 * 10f64.powi(exp as i32)
+            };
+            (cm, "cm")
+        }
+        _ => (m.real_value(), m.meastype.unit()?),
+    };
+    Some(Quantity {
+        value,
+        unit,
+        system: UCUM,
+        code: unit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{measure_body, measure_group, objective, weigh_in};
+    use pretty_assertions::assert_eq;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_bundle_matches_fixture() -> anyhow::Result<()> {
+        let mut scale = weigh_in();
+        scale["measures"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({"value": 215, "type": 6, "unit": -1}));
+        let pressure = json!([
+            {"value": 128, "type": 10, "unit": 0},
+            {"value": 84, "type": 9, "unit": 0},
+            {"value": 67, "type": 11, "unit": 0}
+        ]);
+        let groups = measure_body(
+            None,
+            vec![
+                scale,
+                measure_group(2, 1644056071, pressure),
+                objective(3, 1644142471),
+            ],
+        )
+        .measuregrps;
+
+        let fixture: Value =
+            serde_json::from_str(include_str!("../tests/fixtures/fhir/bundle.json"))?;
+        assert_eq!(
+            serde_json::to_value(bundle("Patient/363", &groups))?,
+            fixture
+        );
+        Ok(())
+    }
+
+    /// Problems of `obs` with the required elements and units of the R4
+    /// vital signs profiles (http://hl7.org/fhir/R4/observation-vitalsigns.html).
+    fn vital_signs_errors(obs: &Value) -> Vec<String> {
+        let mut errors = vec![];
+        let is_vital_sign = obs["category"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|c| c["coding"].as_array().into_iter().flatten())
+            .any(|c| c["system"] == OBSERVATION_CATEGORY && c["code"] == "vital-signs");
+        if !is_vital_sign {
+            errors.push("no vital-signs category".to_string());
+        }
+        if !obs["status"].is_string() {
+            errors.push("no status".into());
+        }
+        if !obs["subject"]["reference"]
+            .as_str()
+            .is_some_and(|r| r.starts_with("Patient/"))
+        {
+            errors.push("subject is not a Patient".into());
+        }
+        // vs-1: a date or more precise.
+        let effective = obs["effectiveDateTime"].as_str().unwrap_or_default();
+        if chrono::DateTime::parse_from_rfc3339(effective).is_err() {
+            errors.push(format!("bad effectiveDateTime {:?}", effective));
+        }
+
+        let code = codes(&obs["code"]);
+        if code.is_empty() {
+            errors.push("no LOINC code".into());
+        }
+        let components = obs["component"].as_array().cloned().unwrap_or_default();
+        // vs-2: a value unless made of components.
+        if components.is_empty() && obs["valueQuantity"].is_null() {
+            errors.push("no value nor component".into());
+        }
+        if code.contains(&"85354-9") {
+            if !obs["valueQuantity"].is_null() {
+                errors.push("blood pressure panel with a value".into());
+            }
+            for pressure in ["8480-6", "8462-4"] {
+                let matching = components
+                    .iter()
+                    .filter(|c| codes(&c["code"]).contains(&pressure))
+                    .collect::<Vec<_>>();
+                if matching.len() != 1 {
+                    errors.push(format!("{} components {}", matching.len(), pressure));
+                }
+                for c in matching {
+                    errors.extend(quantity_errors(&c["valueQuantity"], &["mm[Hg]"]));
+                }
+            }
+        }
+        let units: &[&str] = match () {
+            _ if code.contains(&"29463-7") => &["kg", "g", "[lb_av]"],
+            _ if code.contains(&"8302-2") => &["cm", "[in_i]"],
+            _ if code.contains(&"8310-5") => &["Cel", "[degF]"],
+            _ if code.contains(&"8867-4") => &["/min"],
+            _ if code.contains(&"2708-6") => &["%"],
+            _ if code.contains(&"8480-6") || code.contains(&"8462-4") => &["mm[Hg]"],
+            _ => &[],
+        };
+        if !units.is_empty() {
+            errors.extend(quantity_errors(&obs["valueQuantity"], units));
+        }
+        errors
+    }
+
+    fn codes(concept: &Value) -> Vec<&str> {
+        concept["coding"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|c| c["system"] == LOINC)
+            .filter_map(|c| c["code"].as_str())
+            .collect()
+    }
+
+    fn quantity_errors(quantity: &Value, units: &[&str]) -> Vec<String> {
+        let code = quantity["code"].as_str().unwrap_or_default();
+        if quantity["value"].is_number()
+            && quantity["unit"].is_string()
+            && quantity["system"] == UCUM
+            && units.contains(&code)
+        {
+            vec![]
+        } else {
+            vec![format!("bad quantity {}, expected {:?}", quantity, units)]
+        }
+    }
+
+    #[test]
+    fn test_bundle_follows_vital_signs_profiles() -> anyhow::Result<()> {
+        let mut scale = weigh_in();
+        scale["measures"] = json!([
+            {"value": 80125, "type": 1, "unit": -3},
+            {"value": 180, "type": 4, "unit": -2},
+            {"value": 97, "type": 54, "unit": 0},
+            {"value": 3650, "type": 71, "unit": -2},
+            {"value": 67, "type": 11, "unit": 0}
+        ]);
+        let pressure = json!([
+            {"value": 128, "type": 10, "unit": 0},
+            {"value": 84, "type": 9, "unit": 0}
+        ]);
+        let groups = measure_body(
+            None,
+            vec![
+                scale,
+                measure_group(2, 1644056071, pressure),
+                // Systolic alone can't make a panel.
+                measure_group(
+                    3,
+                    1644142471,
+                    json!([{"value": 131, "type": 10, "unit": 0}]),
+                ),
+                measure_group(4, u64::MAX, json!([{"value": 80, "type": 1, "unit": 0}])),
+            ],
+        )
+        .measuregrps;
+
+        let bundle = serde_json::to_value(bundle("Patient/363", &groups))?;
+        let observations = bundle["entry"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| &e["resource"])
+            .collect::<Vec<_>>();
+        for obs in &observations {
+            assert_eq!(vital_signs_errors(obs), Vec::<String>::new(), "{}", obs);
+        }
+        assert_eq!(
+            observations
+                .iter()
+                .map(|obs| obs["identifier"][0]["value"].as_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["1-1", "1-4", "1-54", "1-71", "1-11", "2-bp", "3-10"]
+        );
+        assert_eq!(observations[1]["valueQuantity"]["value"], json!(180.0));
+        assert_eq!(codes(&observations[2]["code"]), vec!["2708-6", "59408-5"]);
+        Ok(())
+    }
+}
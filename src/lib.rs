@@ -1,5 +1,35 @@
 pub const WITHINGS_API_URL: &str = "https://wbsapi.withings.net";
+pub const WITHINGS_ACCOUNT_URL: &str = "https://account.withings.com";
 
 pub mod api;
+#[cfg(feature = "arrow")]
+pub mod arrow;
 pub mod auth;
+pub mod backfill;
+pub mod blood_pressure;
+pub mod client;
+pub mod de;
 pub mod error;
+#[cfg(feature = "csv")]
+pub mod export;
+#[cfg(feature = "fhir")]
+pub mod fhir;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "omh")]
+pub mod omh;
+pub mod params;
+pub mod rate_limit;
+pub mod response;
+pub mod retry;
+pub mod secret;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod sync;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "chrono")]
+pub mod time;
+
+pub use client::{WithingsClient, WithingsClientBuilder};
+pub use error::{Error, Result};
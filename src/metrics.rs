@@ -0,0 +1,416 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+
+use crate::api::activity::Activity;
+use crate::api::intraday::IntradayActivityBody;
+use crate::api::measure::{Category, MeasureGroup, MeasureType};
+use crate::time::{parse_timezone, start_of_day, to_timestamp};
+
+/// A field value of line protocol, written `80.1` or `8123i`.
+enum FieldValue {
+    Float(f64),
+    Integer(u64),
+}
+
+/// InfluxDB line protocol, one line per measure, e.g.
+/// `weight,category=real,deviceid=cc50f3,unit=kg,user_id=363 value=80.125 1643969671000000000`.
+/// The measurement is the measure type name, or `type_<code>` if unknown.
+/// A type measured several times in a group, such as one segmental measure
+/// per body part, also gets a `position` tag counting from 0, so that the
+/// points don't overwrite each other.
+pub fn measures_line_protocol(user_id: u64, groups: &[MeasureGroup]) -> String {
+    let mut out = String::new();
+    for grp in groups {
+        for (m, position) in grp.measures.iter().zip(positions(grp)) {
+            let category = grp.category.name().map(str::to_string);
+            let mut tags = vec![(
+                "category",
+                category.unwrap_or_else(|| grp.category.code().to_string()),
+            )];
+            if let Some(deviceid) = &grp.deviceid {
+                tags.push(("deviceid", deviceid.clone()));
+            }
+            if let Some(position) = position {
+                tags.push(("position", position.to_string()));
+            }
+            if let Some(unit) = m.meastype.unit() {
+                tags.push(("unit", unit.to_string()));
+            }
+            tags.push(("user_id", user_id.to_string()));
+            write_line(
+                &mut out,
+                &type_name(m.meastype),
+                &tags,
+                &[("value", Some(FieldValue::Float(m.real_value())))],
+                grp.date,
+            );
+        }
+    }
+    out
+}
+
+/// Position of each measure of `grp` among the measures of its type,
+/// counting from 0, or `None` if its type occurs once in the group.
+fn positions(grp: &MeasureGroup) -> Vec<Option<usize>> {
+    let mut counts = HashMap::<u64, usize>::new();
+    for m in &grp.measures {
+        *counts.entry(m.meastype.code()).or_default() += 1;
+    }
+    let mut next = HashMap::<u64, usize>::new();
+    grp.measures
+        .iter()
+        .map(|m| {
+            let code = m.meastype.code();
+            (counts[&code] > 1).then(|| {
+                let position = next.entry(code).or_default();
+                *position += 1;
+                *position - 1
+            })
+        })
+        .collect()
+}
+
+/// InfluxDB line protocol, one `activity` line per day at local midnight
+/// of its `date` in its `timezone`, UTC if unknown.
+pub fn activities_line_protocol(user_id: u64, activities: &[Activity]) -> String {
+    let mut out = String::new();
+    for a in activities {
+        let tz = a
+            .timezone
+            .as_deref()
+            .and_then(parse_timezone)
+            .unwrap_or(Tz::UTC);
+        let Some(start) = NaiveDate::try_from(a.date)
+            .ok()
+            .and_then(|date| start_of_day(date, &tz))
+        else {
+            continue;
+        };
+        let mut tags = vec![];
+        if let Some(deviceid) = &a.deviceid {
+            tags.push(("deviceid", deviceid.clone()));
+        }
+        tags.push(("user_id", user_id.to_string()));
+        write_line(
+            &mut out,
+            "activity",
+            &tags,
+            &[
+                ("steps", a.steps.map(FieldValue::Integer)),
+                ("distance", a.distance.map(FieldValue::Float)),
+                ("elevation", a.elevation.map(FieldValue::Float)),
+                ("soft", a.soft.map(FieldValue::Integer)),
+                ("moderate", a.moderate.map(FieldValue::Integer)),
+                ("intense", a.intense.map(FieldValue::Integer)),
+                ("active", a.active.map(FieldValue::Integer)),
+                ("calories", a.calories.map(FieldValue::Float)),
+                ("totalcalories", a.totalcalories.map(FieldValue::Float)),
+                ("hr_average", a.hr_average.map(FieldValue::Integer)),
+                ("hr_min", a.hr_min.map(FieldValue::Integer)),
+                ("hr_max", a.hr_max.map(FieldValue::Integer)),
+            ],
+            to_timestamp(&start),
+        );
+    }
+    out
+}
+
+/// InfluxDB line protocol, one `intraday_activity` line per sample.
+pub fn intraday_line_protocol(user_id: u64, body: &IntradayActivityBody) -> String {
+    let mut out = String::new();
+    for (t, s) in &body.series {
+        let mut tags = vec![];
+        if let Some(deviceid) = &s.deviceid {
+            tags.push(("deviceid", deviceid.clone()));
+        }
+        if let Some(model) = &s.model {
+            tags.push(("model", model.clone()));
+        }
+        tags.push(("user_id", user_id.to_string()));
+        write_line(
+            &mut out,
+            "intraday_activity",
+            &tags,
+            &[
+                ("steps", s.steps.map(FieldValue::Integer)),
+                ("elevation", s.elevation.map(FieldValue::Float)),
+                ("calories", s.calories.map(FieldValue::Float)),
+                ("distance", s.distance.map(FieldValue::Float)),
+                ("stroke", s.stroke.map(FieldValue::Integer)),
+                ("pool_lap", s.pool_lap.map(FieldValue::Integer)),
+                ("duration", s.duration.map(FieldValue::Integer)),
+                ("heart_rate", s.heart_rate.map(FieldValue::Integer)),
+                ("spo2_auto", s.spo2_auto.map(FieldValue::Float)),
+            ],
+            *t,
+        );
+    }
+    out
+}
+
+/// Prometheus text exposition of the latest real measure per user and type,
+/// e.g. `withings_weight{unit="kg",user_id="363"} 80.125`, along with
+/// `withings_<type>_timestamp_seconds` of when it was taken. A type measured
+/// several times in a group gets a `position` label, as in
+/// [`measures_line_protocol`]. Samples carry no timestamp, since Prometheus
+/// drops samples older than its head block.
+pub fn latest_measures_prometheus<'a>(
+    groups: impl IntoIterator<Item = (u64, &'a MeasureGroup)>,
+) -> String {
+    // type code -> (user, position) -> (date, value)
+    let mut latest = BTreeMap::<u64, BTreeMap<(u64, Option<usize>), (u64, f64)>>::new();
+    for (user_id, grp) in groups {
+        if grp.category != Category::Real {
+            continue;
+        }
+        for (m, position) in grp.measures.iter().zip(positions(grp)) {
+            let entry = latest
+                .entry(m.meastype.code())
+                .or_default()
+                .entry((user_id, position))
+                .or_insert((grp.date, m.real_value()));
+            if grp.date > entry.0 {
+                *entry = (grp.date, m.real_value());
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for (code, series) in latest {
+        let meastype = MeasureType::from(code);
+        let name = format!("withings_{}", type_name(meastype));
+        let unit = meastype.unit();
+        let labels = |(user_id, position): (u64, Option<usize>), unit: Option<&str>| {
+            let mut labels = vec![];
+            if let Some(position) = position {
+                labels.push(format!("position=\"{}\"", position));
+            }
+            if let Some(unit) = unit {
+                labels.push(format!("unit=\"{}\"", escape_label(unit)));
+            }
+            labels.push(format!("user_id=\"{}\"", user_id));
+            format!("{{{}}}", labels.join(","))
+        };
+
+        let _ = writeln!(
+            out,
+            "# HELP {} Latest {} measure.",
+            name,
+            type_name(meastype)
+        );
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for (key, (_, value)) in &series {
+            let _ = writeln!(out, "{}{} {}", name, labels(*key, unit), value);
+        }
+        let _ = writeln!(
+            out,
+            "# HELP {}_timestamp_seconds When the latest {} measure was taken.",
+            name,
+            type_name(meastype)
+        );
+        let _ = writeln!(out, "# TYPE {}_timestamp_seconds gauge", name);
+        for (key, (date, _)) in &series {
+            let _ = writeln!(
+                out,
+                "{}_timestamp_seconds{} {}",
+                name,
+                labels(*key, None),
+                date
+            );
+        }
+    }
+    out
+}
+
+/// Appends a line unless all `fields` are missing. Tags are written in the
+/// given order, which should be sorted by key as InfluxDB recommends.
+fn write_line(
+    out: &mut String,
+    measurement: &str,
+    tags: &[(&str, String)],
+    fields: &[(&str, Option<FieldValue>)],
+    secs: u64,
+) {
+    let fields = fields
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = match value.as_ref()? {
+                FieldValue::Float(v) => v.to_string(),
+                FieldValue::Integer(v) => format!("{}i", v),
+            };
+            Some(format!("{}={}", escape_key(key), value))
+        })
+        .collect::<Vec<_>>();
+    if fields.is_empty() {
+        return;
+    }
+
+    out.push_str(&escape(measurement, &[',', ' ']));
+    for (key, value) in tags {
+        let _ = write!(out, ",{}={}", escape_key(key), escape_key(value));
+    }
+    let _ = writeln!(
+        out,
+        " {} {}",
+        fields.join(","),
+        u128::from(secs) * 1_000_000_000
+    );
+}
+
+fn type_name(meastype: MeasureType) -> String {
+    meastype
+        .name()
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("type_{}", meastype.code()))
+}
+
+/// Tag keys, tag values and field keys.
+fn escape_key(s: &str) -> String {
+    escape(s, &[',', '=', ' '])
+}
+
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn escape_label(s: &str) -> String {
+    s.replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{measure_body, measure_group, objective, weigh_in};
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn groups() -> Vec<MeasureGroup> {
+        measure_body(
+            None,
+            vec![
+                weigh_in(),
+                measure_group(
+                    2,
+                    1644056071,
+                    json!([{"value": 795, "type": 1, "unit": -1}]),
+                ),
+                objective(3, 1644142471),
+            ],
+        )
+        .measuregrps
+    }
+
+    #[test]
+    fn test_measures_line_protocol() {
+        assert_eq!(
+            measures_line_protocol(363, &groups()[..1]),
+            "weight,category=real,deviceid=cc50f3,unit=kg,user_id=363 value=80.125 1643969671000000000\n\
+             type_999,category=real,deviceid=cc50f3,user_id=363 value=42 1643969671000000000\n"
+        );
+
+        let segments = measure_body(
+            None,
+            vec![measure_group(
+                4,
+                1644056071,
+                json!([
+                    {"value": 1040, "type": 173, "unit": -2},
+                    {"value": 1020, "type": 173, "unit": -2}
+                ]),
+            )],
+        );
+        assert_eq!(
+            measures_line_protocol(363, &segments.measuregrps),
+            "fat_free_mass_segments,category=real,position=0,unit=kg,user_id=363 value=10.4 1644056071000000000\n\
+             fat_free_mass_segments,category=real,position=1,unit=kg,user_id=363 value=10.2 1644056071000000000\n"
+        );
+    }
+
+    #[test]
+    fn test_activity_and_intraday_line_protocol() -> anyhow::Result<()> {
+        let activities: Vec<Activity> = serde_json::from_value(json!([
+            {"date": "2022-02-04", "deviceid": "my device", "steps": 8123, "distance": 6012.5},
+            {"date": "2022-02-05", "timezone": "Asia/Tokyo", "steps": 4051},
+            {"date": "2022-02-06"}
+        ]))?;
+        // Local midnight, 2022-02-04T15:00:00Z in Tokyo.
+        assert_eq!(
+            activities_line_protocol(363, &activities),
+            "activity,deviceid=my\\ device,user_id=363 steps=8123i,distance=6012.5 1643932800000000000\n\
+             activity,user_id=363 steps=4051i 1643986800000000000\n"
+        );
+
+        let intraday: IntradayActivityBody = serde_json::from_value(json!({
+            "series": {
+                "1643969640": {"model": "ScanWatch", "steps": 41, "calories": 1.52, "duration": 60}
+            }
+        }))?;
+        assert_eq!(
+            intraday_line_protocol(363, &intraday),
+            "intraday_activity,model=ScanWatch,user_id=363 steps=41i,calories=1.52,duration=60i 1643969640000000000\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest_measures_prometheus() {
+        let groups = groups();
+        let other = &groups[0];
+        let exposition =
+            latest_measures_prometheus(groups.iter().map(|grp| (363, grp)).chain([(17, other)]));
+        assert_eq!(
+            exposition,
+            "# HELP withings_weight Latest weight measure.\n\
+             # TYPE withings_weight gauge\n\
+             withings_weight{unit=\"kg\",user_id=\"17\"} 80.125\n\
+             withings_weight{unit=\"kg\",user_id=\"363\"} 79.5\n\
+             # HELP withings_weight_timestamp_seconds When the latest weight measure was taken.\n\
+             # TYPE withings_weight_timestamp_seconds gauge\n\
+             withings_weight_timestamp_seconds{user_id=\"17\"} 1643969671\n\
+             withings_weight_timestamp_seconds{user_id=\"363\"} 1644056071\n\
+             # HELP withings_type_999 Latest type_999 measure.\n\
+             # TYPE withings_type_999 gauge\n\
+             withings_type_999{user_id=\"17\"} 42\n\
+             withings_type_999{user_id=\"363\"} 42\n\
+             # HELP withings_type_999_timestamp_seconds When the latest type_999 measure was taken.\n\
+             # TYPE withings_type_999_timestamp_seconds gauge\n\
+             withings_type_999_timestamp_seconds{user_id=\"17\"} 1643969671\n\
+             withings_type_999_timestamp_seconds{user_id=\"363\"} 1643969671\n"
+        );
+
+        let segments = measure_body(
+            None,
+            vec![measure_group(
+                4,
+                1644056071,
+                json!([
+                    {"value": 1040, "type": 173, "unit": -2},
+                    {"value": 1020, "type": 173, "unit": -2}
+                ]),
+            )],
+        );
+        assert_eq!(
+            latest_measures_prometheus(segments.measuregrps.iter().map(|grp| (363, grp))),
+            "# HELP withings_fat_free_mass_segments Latest fat_free_mass_segments measure.\n\
+             # TYPE withings_fat_free_mass_segments gauge\n\
+             withings_fat_free_mass_segments{position=\"0\",unit=\"kg\",user_id=\"363\"} 10.4\n\
+             withings_fat_free_mass_segments{position=\"1\",unit=\"kg\",user_id=\"363\"} 10.2\n\
+             # HELP withings_fat_free_mass_segments_timestamp_seconds When the latest fat_free_mass_segments measure was taken.\n\
+             # TYPE withings_fat_free_mass_segments_timestamp_seconds gauge\n\
+             withings_fat_free_mass_segments_timestamp_seconds{position=\"0\",user_id=\"363\"} 1644056071\n\
+             withings_fat_free_mass_segments_timestamp_seconds{position=\"1\",user_id=\"363\"} 1644056071\n"
+        );
+    }
+}
@@ -0,0 +1,384 @@
+use chrono::{DateTime, SecondsFormat, TimeZone};
+use chrono_tz::Tz;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::api::activity::Activity;
+use crate::api::measure::{Category, MeasureBody, MeasureGroup, MeasureType};
+use crate::api::sleep::SleepSummary;
+use crate::time::{from_timestamp, parse_timezone, start_of_day};
+
+const SOURCE_NAME: &str = "Withings";
+
+/// Open mHealth data point: a header and a body following `schema_id`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DataPoint {
+    pub header: Header,
+    pub body: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Header {
+    /// `withings-<user>-<measure|activity|sleep>-<record>-<schema>`, derived
+    /// from the Withings ids so that converting the same record again gives
+    /// the same id.
+    pub id: String,
+    pub creation_date_time: String,
+    pub schema_id: SchemaId,
+    pub acquisition_provenance: Provenance,
+    pub user_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaId {
+    pub namespace: &'static str,
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Provenance {
+    pub source_name: &'static str,
+    pub source_creation_date_time: String,
+    /// `sensed`, or `self-reported` for measures entered by hand.
+    pub modality: &'static str,
+}
+
+/// `body-weight`, `blood-pressure`, `heart-rate`, `body-temperature` and
+/// `oxygen-saturation` data points of the real measure groups. Blood
+/// pressure needs both systolic and diastolic in the group.
+pub fn measure_data_points(user_id: u64, body: &MeasureBody) -> Vec<DataPoint> {
+    let tz = body.tz().unwrap_or(Tz::UTC);
+    let mut points = vec![];
+    for grp in &body.measuregrps {
+        if grp.category != Category::Real {
+            continue;
+        }
+        let value = |t: MeasureType| {
+            grp.measures
+                .iter()
+                .find(|m| m.meastype == t)
+                .map(|m| m.real_value())
+        };
+        let (Some(date), Some(created)) = (datetime(grp.date, &tz), datetime(grp.created, &tz))
+        else {
+            continue;
+        };
+        let at = json!({"date_time": date});
+        let mut point = |name, body| points.push(measure_point(user_id, grp, &created, name, body));
+
+        if let Some(kg) = value(MeasureType::Weight) {
+            point(
+                "body-weight",
+                json!({"body_weight": {"value": kg, "unit": "kg"}, "effective_time_frame": at}),
+            );
+        }
+        if let (Some(systolic), Some(diastolic)) = (
+            value(MeasureType::SystolicBloodPressure),
+            value(MeasureType::DiastolicBloodPressure),
+        ) {
+            point(
+                "blood-pressure",
+                json!({
+                    "systolic_blood_pressure": {"value": systolic, "unit": "mmHg"},
+                    "diastolic_blood_pressure": {"value": diastolic, "unit": "mmHg"},
+                    "effective_time_frame": at
+                }),
+            );
+        }
+        if let Some(bpm) = value(MeasureType::HeartPulse) {
+            point(
+                "heart-rate",
+                json!({
+                    "heart_rate": {"value": bpm, "unit": "beats/min"},
+                    "effective_time_frame": at
+                }),
+            );
+        }
+        if let Some(celsius) =
+            value(MeasureType::BodyTemperature).or_else(|| value(MeasureType::Temperature))
+        {
+            point(
+                "body-temperature",
+                json!({
+                    "body_temperature": {"value": celsius, "unit": "C"},
+                    "effective_time_frame": at
+                }),
+            );
+        }
+        if let Some(percent) = value(MeasureType::Spo2) {
+            point(
+                "oxygen-saturation",
+                json!({
+                    "oxygen_saturation": {"value": percent, "unit": "%"},
+                    "measurement_system": "peripheral capillary",
+                    "effective_time_frame": at
+                }),
+            );
+        }
+    }
+    points
+}
+
+/// `step-count` data points, one per day starting at local midnight.
+pub fn activity_data_points(user_id: u64, activities: &[Activity]) -> Vec<DataPoint> {
+    activities
+        .iter()
+        .filter_map(|a| {
+            let steps = a.steps?;
+            let tz = tz(a.timezone.as_deref());
+            let date = chrono::NaiveDate::try_from(a.date).ok()?;
+            let start = format(&start_of_day(date, &tz)?);
+            Some(DataPoint {
+                header: header(
+                    format!("withings-{}-activity-{}-step-count", user_id, a.date),
+                    user_id,
+                    "step-count",
+                    start.clone(),
+                    "sensed",
+                ),
+                body: json!({
+                    "step_count": steps,
+                    "effective_time_frame": {
+                        "time_interval": {
+                            "start_date_time": start,
+                            "duration": {"value": 1, "unit": "d"}
+                        }
+                    }
+                }),
+            })
+        })
+        .collect()
+}
+
+/// `sleep-duration` data points over each night, from `total_sleep_time`
+/// or else the sum of light, deep and REM sleep.
+pub fn sleep_data_points(user_id: u64, summaries: &[SleepSummary]) -> Vec<DataPoint> {
+    summaries
+        .iter()
+        .filter_map(|s| {
+            let d = &s.data;
+            let seconds = d.total_sleep_time.or_else(|| {
+                [
+                    d.lightsleepduration,
+                    d.deepsleepduration,
+                    d.remsleepduration,
+                ]
+                .into_iter()
+                .flatten()
+                .reduce(|a, b| a + b)
+            })?;
+            let tz = tz(s.timezone.as_deref());
+            Some(DataPoint {
+                header: header(
+                    format!("withings-{}-sleep-{}-sleep-duration", user_id, s.id),
+                    user_id,
+                    "sleep-duration",
+                    datetime(s.created, &tz)?,
+                    "sensed",
+                ),
+                body: json!({
+                    "sleep_duration": {"value": seconds, "unit": "sec"},
+                    "effective_time_frame": {
+                        "time_interval": {
+                            "start_date_time": datetime(s.startdate, &tz)?,
+                            "end_date_time": datetime(s.enddate, &tz)?
+                        }
+                    }
+                }),
+            })
+        })
+        .collect()
+}
+
+fn measure_point(
+    user_id: u64,
+    grp: &MeasureGroup,
+    created: &str,
+    name: &'static str,
+    body: Value,
+) -> DataPoint {
+    // attrib 2 and 4 are measures entered by the user.
+    let modality = match grp.attrib {
+        2 | 4 => "self-reported",
+        _ => "sensed",
+    };
+    DataPoint {
+        header: header(
+            format!("withings-{}-measure-{}-{}", user_id, grp.grpid, name),
+            user_id,
+            name,
+            created.to_string(),
+            modality,
+        ),
+        body,
+    }
+}
+
+fn header(
+    id: String,
+    user_id: u64,
+    name: &'static str,
+    created: String,
+    modality: &'static str,
+) -> Header {
+    Header {
+        id,
+        creation_date_time: created.clone(),
+        schema_id: SchemaId {
+            namespace: "omh",
+            name,
+            version: "2.0",
+        },
+        acquisition_provenance: Provenance {
+            source_name: SOURCE_NAME,
+            source_creation_date_time: created,
+            modality,
+        },
+        user_id: user_id.to_string(),
+    }
+}
+
+fn tz(name: Option<&str>) -> Tz {
+    name.and_then(parse_timezone).unwrap_or(Tz::UTC)
+}
+
+/// `None` if `secs` is out of range, in which case the data point is
+/// skipped rather than written with an invalid date.
+fn datetime(secs: u64, tz: &Tz) -> Option<String> {
+    from_timestamp(secs, tz).map(|at| format(&at))
+}
+
+fn format<Z: TimeZone>(at: &DateTime<Z>) -> String
+where
+    Z::Offset: std::fmt::Display,
+{
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{measure_body, measure_group, objective, weigh_in};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_measure_data_points() -> anyhow::Result<()> {
+        // Entered by hand.
+        let mut scale = weigh_in();
+        scale["attrib"] = json!(2);
+        let pressure = json!([
+            {"value": 128, "type": 10, "unit": 0},
+            {"value": 84, "type": 9, "unit": 0},
+            {"value": 67, "type": 11, "unit": 0}
+        ]);
+        let body = measure_body(
+            Some("Europe/Paris"),
+            vec![
+                scale,
+                measure_group(2, 1644056071, pressure),
+                objective(3, 1644142471),
+                // Out of range, so it has no valid date.
+                measure_group(4, u64::MAX, json!([{"value": 80, "type": 1, "unit": 0}])),
+            ],
+        );
+
+        let points = measure_data_points(363, &body);
+        assert_eq!(
+            points
+                .iter()
+                .map(|p| p.header.schema_id.name)
+                .collect::<Vec<_>>(),
+            vec!["body-weight", "blood-pressure", "heart-rate"]
+        );
+        assert_eq!(
+            serde_json::to_value(&points[1])?,
+            json!({
+                "header": {
+                    "id": "withings-363-measure-2-blood-pressure",
+                    "creation_date_time": "2022-02-05T11:14:31+01:00",
+                    "schema_id": {"namespace": "omh", "name": "blood-pressure", "version": "2.0"},
+                    "acquisition_provenance": {
+                        "source_name": "Withings",
+                        "source_creation_date_time": "2022-02-05T11:14:31+01:00",
+                        "modality": "sensed"
+                    },
+                    "user_id": "363"
+                },
+                "body": {
+                    "systolic_blood_pressure": {"value": 128.0, "unit": "mmHg"},
+                    "diastolic_blood_pressure": {"value": 84.0, "unit": "mmHg"},
+                    "effective_time_frame": {"date_time": "2022-02-05T11:14:31+01:00"}
+                }
+            })
+        );
+        assert_eq!(
+            points[0].header.acquisition_provenance.modality,
+            "self-reported"
+        );
+        assert_eq!(
+            points[0].body["body_weight"],
+            json!({"value": 80.125, "unit": "kg"})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_activity_and_sleep_data_points() -> anyhow::Result<()> {
+        let activities: Vec<Activity> = serde_json::from_value(json!([
+            {"date": "2022-02-04", "timezone": "Asia/Tokyo", "steps": 8123},
+            {"date": "2022-02-05", "timezone": "Asia/Tokyo"}
+        ]))?;
+        let points = activity_data_points(363, &activities);
+        assert_eq!(points.len(), 1);
+        assert_eq!(
+            points[0].header.id,
+            "withings-363-activity-2022-02-04-step-count"
+        );
+        assert_eq!(
+            points[0].body,
+            json!({
+                "step_count": 8123,
+                "effective_time_frame": {
+                    "time_interval": {
+                        "start_date_time": "2022-02-04T00:00:00+09:00",
+                        "duration": {"value": 1, "unit": "d"}
+                    }
+                }
+            })
+        );
+
+        let summaries: Vec<SleepSummary> = serde_json::from_value(json!([{
+            "id": 2081804,
+            "startdate": 1643899800,
+            "enddate": 1643926500,
+            "date": "2022-02-04",
+            "created": 1643927120,
+            "modified": 1643927130,
+            "data": {"lightsleepduration": 12000, "deepsleepduration": 5400, "remsleepduration": 4800}
+        }, {
+            "id": 2081805,
+            "startdate": 1643986200,
+            "enddate": u64::MAX,
+            "date": "2022-02-05",
+            "created": 1644013520,
+            "modified": 1644013530,
+            "data": {"total_sleep_time": 21600}
+        }]))?;
+        let points = sleep_data_points(363, &summaries);
+        assert_eq!(points.len(), 1);
+        assert_eq!(
+            points[0].header.id,
+            "withings-363-sleep-2081804-sleep-duration"
+        );
+        assert_eq!(
+            points[0].body["sleep_duration"],
+            json!({"value": 22200, "unit": "sec"})
+        );
+        assert_eq!(
+            points[0].body["effective_time_frame"]["time_interval"]["start_date_time"],
+            "2022-02-03T14:50:00Z"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Value of a single request parameter.
+pub trait ToParam {
+    fn to_param(&self) -> String;
+}
+
+impl ToParam for u64 {
+    fn to_param(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl ToParam for String {
+    fn to_param(&self) -> String {
+        self.clone()
+    }
+}
+
+/// `serialize_with` helper encoding `Some(vec![1, 4])` as `1,4`, since
+/// `serde_urlencoded` can't encode sequences. `None` is omitted.
+pub fn comma_separated<S, T>(values: &Option<Vec<T>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: ToParam,
+{
+    match values {
+        Some(values) => serializer.serialize_str(
+            &values
+                .iter()
+                .map(ToParam::to_param)
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Calendar date sent as `YYYY-MM-DD`, e.g. `startdateymd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct YmdDate {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl YmdDate {
+    pub fn new(year: i32, month: u32, day: u32) -> YmdDate {
+        YmdDate { year, month, day }
+    }
+
+    /// UTC date of epoch seconds.
+    pub fn from_timestamp(secs: u64) -> YmdDate {
+        // Days to civil date, from Howard Hinnant's `civil_from_days`.
+        let z = (secs / 86400) as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + i64::from(month <= 2)) as i32;
+        YmdDate { year, month, day }
+    }
+
+    /// Epoch seconds of midnight UTC, 0 before 1970.
+    pub fn to_timestamp(&self) -> u64 {
+        // Civil date to days, from Howard Hinnant's `days_from_civil`.
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let month = i64::from(self.month);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5
+            + i64::from(self.day)
+            - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146097 + doe - 719468;
+        (days * 86400).max(0) as u64
+    }
+}
+
+impl fmt::Display for YmdDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl FromStr for YmdDate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<YmdDate, String> {
+        let invalid = || format!("invalid date: {}", s);
+        let mut parts = s.split('-');
+        let mut next = || {
+            parts
+                .next()
+                .and_then(|part| part.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let (year, month, day) = (next()?, next()?, next()?);
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return Err(invalid());
+        }
+        Ok(YmdDate {
+            year: i32::try_from(year).map_err(|_| invalid())?,
+            month: month as u32,
+            day: day as u32,
+        })
+    }
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl ToParam for YmdDate {
+    fn to_param(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Serialize for YmdDate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for YmdDate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<YmdDate, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Params {
+        action: String,
+        #[serde(serialize_with = "comma_separated")]
+        ids: Option<Vec<u64>>,
+        date: Option<YmdDate>,
+        offset: Option<u64>,
+    }
+
+    #[test]
+    fn test_encode_params() -> anyhow::Result<()> {
+        let params = Params {
+            action: "get".into(),
+            ids: Some(vec![1, 4, 9]),
+            date: Some(YmdDate::new(2022, 2, 4)),
+            offset: None,
+        };
+        assert_eq!(
+            serde_urlencoded::to_string(&params)?,
+            "action=get&ids=1%2C4%2C9&date=2022-02-04"
+        );
+
+        let params = Params {
+            action: "get".into(),
+            ids: None,
+            date: None,
+            offset: Some(3),
+        };
+        assert_eq!(serde_urlencoded::to_string(&params)?, "action=get&offset=3");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ymd_date() {
+        assert_eq!(YmdDate::new(2022, 2, 4).to_string(), "2022-02-04");
+        assert_eq!("2022-02-04".parse(), Ok(YmdDate::new(2022, 2, 4)));
+        assert!("2022-02".parse::<YmdDate>().is_err());
+        assert!("2022-xx-04".parse::<YmdDate>().is_err());
+        assert!("2022-02-04-extra".parse::<YmdDate>().is_err());
+        assert!("2022-13-04".parse::<YmdDate>().is_err());
+        assert!("2022-00-04".parse::<YmdDate>().is_err());
+        assert!("2022-02-00".parse::<YmdDate>().is_err());
+        assert!("2022-02-29".parse::<YmdDate>().is_err());
+        assert!("2022-04-31".parse::<YmdDate>().is_err());
+        assert!("1900-02-29".parse::<YmdDate>().is_err());
+        assert_eq!("2000-02-29".parse(), Ok(YmdDate::new(2000, 2, 29)));
+        assert_eq!("2022-12-31".parse(), Ok(YmdDate::new(2022, 12, 31)));
+        assert_eq!(YmdDate::from_timestamp(0), YmdDate::new(1970, 1, 1));
+        assert_eq!(
+            YmdDate::from_timestamp(951782400),
+            YmdDate::new(2000, 2, 29)
+        );
+        assert_eq!(
+            YmdDate::from_timestamp(1644019199),
+            YmdDate::new(2022, 2, 4)
+        );
+        assert_eq!(YmdDate::new(2022, 2, 4).to_timestamp(), 1643932800);
+        assert_eq!(YmdDate::new(2000, 2, 29).to_timestamp(), 951782400);
+        assert_eq!(YmdDate::new(1969, 12, 31).to_timestamp(), 0);
+        assert_eq!(
+            serde_json::from_str::<YmdDate>(r#""2022-02-04""#).ok(),
+            Some(YmdDate::new(2022, 2, 4))
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock, RwLock, Weak};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{Error, Result};
+
+/// Request quotas enforced client-side before calling Withings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    /// Requests per minute for the whole application.
+    pub requests_per_minute: u32,
+    /// Requests per minute for a single user, when set.
+    pub per_user_requests_per_minute: Option<u32>,
+}
+
+/// Time a request spent waiting for the rate limiter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitWait {
+    pub action: String,
+    pub user_id: Option<u64>,
+    pub waited: Duration,
+}
+
+type Observer = Arc<dyn Fn(&RateLimitWait) + Send + Sync>;
+
+/// Token-bucket rate limiter. Clones share the same buckets.
+#[derive(Clone)]
+pub struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    limit: RateLimit,
+    app: Mutex<Bucket>,
+    users: Mutex<HashMap<u64, Bucket>>,
+    observer: RwLock<Option<Observer>>,
+}
+
+struct Bucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    updated: Instant,
+}
+
+static SHARED: OnceLock<Mutex<HashMap<String, Weak<Inner>>>> = OnceLock::new();
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            inner: Arc::new(Inner {
+                app: Mutex::new(Bucket::new(limit.requests_per_minute)),
+                users: Mutex::new(HashMap::new()),
+                observer: RwLock::new(None),
+                limit,
+            }),
+        }
+    }
+
+    /// Limiter shared by every client of the application `client_id`, as
+    /// long as a client still holds it. Fails with `Error::Config` if it is
+    /// already shared with a different limit.
+    pub fn shared(client_id: &str, limit: RateLimit) -> Result<RateLimiter> {
+        let mut shared = SHARED
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        if let Some(inner) = shared.get(client_id).and_then(Weak::upgrade) {
+            if inner.limit != limit {
+                return Err(Error::Config(format!(
+                    "rate limit of {} is already {:?}, not {:?}",
+                    client_id, inner.limit, limit
+                )));
+            }
+            return Ok(RateLimiter { inner });
+        }
+
+        let limiter = RateLimiter::new(limit);
+        shared.retain(|_, inner| inner.strong_count() > 0);
+        shared.insert(client_id.into(), Arc::downgrade(&limiter.inner));
+        Ok(limiter)
+    }
+
+    pub fn limit(&self) -> &RateLimit {
+        &self.inner.limit
+    }
+
+    /// Called whenever a request had to wait for the limiter.
+    pub fn set_observer(&self, observer: impl Fn(&RateLimitWait) + Send + Sync + 'static) {
+        *self
+            .inner
+            .observer
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(observer));
+    }
+
+    /// Wait until a request for `user_id` fits in the quotas and return how
+    /// long it waited.
+    pub async fn acquire(&self, user_id: Option<u64>) -> Duration {
+        let mut waited = Duration::ZERO;
+
+        // Take the app-wide token only once the user's turn has come, so a
+        // throttled user doesn't hold back the other users.
+        if let (Some(user_id), Some(per_user)) =
+            (user_id, self.inner.limit.per_user_requests_per_minute)
+        {
+            let wait = self
+                .inner
+                .users
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(user_id)
+                .or_insert_with(|| Bucket::new(per_user))
+                .reserve(Instant::now());
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+                waited += wait;
+            }
+        }
+
+        let wait = self
+            .inner
+            .app
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .reserve(Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+            waited += wait;
+        }
+        waited
+    }
+
+    pub(crate) async fn acquire_for(&self, action: &str, user_id: Option<u64>) {
+        let waited = self.acquire(user_id).await;
+        if waited.is_zero() {
+            return;
+        }
+
+        let observer = self
+            .inner
+            .observer
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        if let Some(observer) = observer {
+            observer(&RateLimitWait {
+                action: action.into(),
+                user_id,
+                waited,
+            });
+        }
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("limit", &self.inner.limit)
+            .finish()
+    }
+}
+
+impl Bucket {
+    fn new(requests_per_minute: u32) -> Bucket {
+        let capacity = requests_per_minute.max(1) as f64;
+        Bucket {
+            capacity,
+            tokens: capacity,
+            per_second: capacity / 60.0,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Take a token and return how long to wait until it is actually
+    /// available. Tokens may go negative so that waiting requests queue up.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+        self.tokens -= 1.0;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.per_second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_waits_when_quota_exhausted() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests_per_minute: 2,
+            per_user_requests_per_minute: None,
+        });
+
+        assert_eq!(limiter.acquire(None).await, Duration::ZERO);
+        assert_eq!(limiter.acquire(None).await, Duration::ZERO);
+
+        let start = Instant::now();
+        assert_eq!(limiter.acquire(None).await, Duration::from_secs(30));
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+
+        assert_eq!(limiter.acquire(None).await, Duration::from_secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_refills_over_time() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests_per_minute: 1,
+            per_user_requests_per_minute: None,
+        });
+
+        assert_eq!(limiter.acquire(None).await, Duration::ZERO);
+        tokio::time::advance(Duration::from_secs(45)).await;
+        assert_eq!(limiter.acquire(None).await, Duration::from_secs(15));
+        tokio::time::advance(Duration::from_secs(120)).await;
+        assert_eq!(limiter.acquire(None).await, Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_per_user_limit() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests_per_minute: 100,
+            per_user_requests_per_minute: Some(1),
+        });
+
+        assert_eq!(limiter.acquire(Some(1)).await, Duration::ZERO);
+        assert_eq!(limiter.acquire(Some(2)).await, Duration::ZERO);
+        assert_eq!(limiter.acquire(None).await, Duration::ZERO);
+        assert_eq!(limiter.acquire(Some(1)).await, Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttled_user_keeps_app_quota() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests_per_minute: 2,
+            per_user_requests_per_minute: Some(1),
+        });
+
+        assert_eq!(limiter.acquire(Some(1)).await, Duration::ZERO);
+        let throttled = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(Some(1)).await }
+        });
+        tokio::task::yield_now().await;
+
+        assert_eq!(limiter.acquire(Some(2)).await, Duration::ZERO);
+        assert_eq!(throttled.await.unwrap(), Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shared_by_client_id() {
+        let limit = RateLimit {
+            requests_per_minute: 1,
+            per_user_requests_per_minute: None,
+        };
+        let a = RateLimiter::shared("test_shared_client", limit.clone()).unwrap();
+        let b = RateLimiter::shared("test_shared_client", limit.clone()).unwrap();
+        let other = RateLimiter::shared("test_other_client", limit).unwrap();
+
+        assert_eq!(a.acquire(None).await, Duration::ZERO);
+        assert_eq!(b.acquire(None).await, Duration::from_secs(60));
+        assert_eq!(other.acquire(None).await, Duration::ZERO);
+
+        let different = RateLimit {
+            requests_per_minute: 2,
+            per_user_requests_per_minute: None,
+        };
+        assert!(matches!(
+            RateLimiter::shared("test_shared_client", different),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_observer() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests_per_minute: 1,
+            per_user_requests_per_minute: None,
+        });
+        let waits = Arc::new(Mutex::new(vec![]));
+        let recorded = waits.clone();
+        limiter.set_observer(move |w| recorded.lock().unwrap().push(w.clone()));
+
+        limiter.acquire_for("getmeas", Some(1)).await;
+        limiter.acquire_for("getmeas", Some(1)).await;
+
+        assert_eq!(
+            *waits.lock().unwrap(),
+            vec![RateLimitWait {
+                action: "getmeas".into(),
+                user_id: Some(1),
+                waited: Duration::from_secs(60),
+            }]
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, DeserializeOwned, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::value::RawValue;
+
+use crate::error::{Error, RequestMeta, WithingsApiError};
+use crate::Result;
+
+/// Envelope of every Withings response.
+///
+/// `body` is only decoded when `status` is 0, so error responses with an
+/// empty or partial body don't fail to decode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithingsResponse<T> {
+    pub status: u64,
+    pub body: Option<T>,
+    pub error: Option<String>,
+}
+
+/// Decode a response body in a single pass and return its `body` or the
+/// error matching its status.
+pub fn decode<T: DeserializeOwned>(req: RequestMeta, bytes: &[u8]) -> Result<T> {
+    into_body(req, serde_json::from_slice(bytes)?, bytes)
+}
+
+/// Like [`decode`], but fails with [`Error::UnknownFields`] when the response
+/// has fields that `T` doesn't map.
+pub fn decode_strict<T: DeserializeOwned>(req: RequestMeta, bytes: &[u8]) -> Result<T> {
+    let mut fields = Vec::new();
+    // The envelope and the body are checked separately, since a body sent
+    // before `status` is only decoded once the envelope is complete.
+    let res: WithingsResponse<Box<RawValue>> = deserialize_reporting(bytes, "", &mut fields)?;
+    let res = WithingsResponse {
+        body: match (res.status, res.body) {
+            (0, Some(raw)) => Some(deserialize_reporting::<T>(
+                raw.get().as_bytes(),
+                "body",
+                &mut fields,
+            )?),
+            _ => None,
+        },
+        status: res.status,
+        error: res.error,
+    };
+
+    let body = into_body(req.clone(), res, bytes)?;
+    fields.sort();
+    if fields.is_empty() {
+        Ok(body)
+    } else {
+        Err(Error::UnknownFields {
+            request: req,
+            fields,
+        })
+    }
+}
+
+fn deserialize_reporting<T: DeserializeOwned>(
+    bytes: &[u8],
+    root: &str,
+    fields: &mut Vec<String>,
+) -> Result<T> {
+    let mut de = serde_json::Deserializer::from_slice(bytes);
+    let value = serde_ignored::deserialize(&mut de, |path| fields.push(field_path(&path, root)))?;
+    de.end()?;
+    Ok(value)
+}
+
+/// `body.measuregrps.0.extra`, without the `?` serde_ignored shows for
+/// `Option`s.
+fn field_path(path: &serde_ignored::Path, root: &str) -> String {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => root.to_string(),
+        Path::Seq { parent, index } => join_path(field_path(parent, root), &index.to_string()),
+        Path::Map { parent, key } => join_path(field_path(parent, root), key),
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => field_path(parent, root),
+    }
+}
+
+fn join_path(parent: String, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+fn into_body<T>(req: RequestMeta, res: WithingsResponse<T>, bytes: &[u8]) -> Result<T> {
+    match res {
+        WithingsResponse {
+            status: 0,
+            body: Some(body),
+            ..
+        } => Ok(body),
+        WithingsResponse { status: 0, .. } => Err(Error::Decode(de::Error::missing_field("body"))),
+        WithingsResponse { status, error, .. } => Err(WithingsApiError::from_body_status(
+            status,
+            error,
+            req,
+            String::from_utf8_lossy(bytes).into_owned(),
+        )
+        .into()),
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for WithingsResponse<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_map(ResponseVisitor(PhantomData))
+    }
+}
+
+struct ResponseVisitor<T>(PhantomData<T>);
+
+impl<'de, T: DeserializeOwned> Visitor<'de> for ResponseVisitor<T> {
+    type Value = WithingsResponse<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a Withings response")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let mut status = None;
+        let mut body = None;
+        // Withings sends `status` first. If it ever doesn't, the body is kept
+        // raw until the status is known.
+        let mut raw_body: Option<Box<RawValue>> = None;
+        let mut error = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "status" => status = Some(map.next_value::<u64>()?),
+                "body" => match status {
+                    Some(0) => body = map.next_value::<Option<T>>()?,
+                    Some(_) => {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                    None => raw_body = Some(map.next_value()?),
+                },
+                "error" => error = map.next_value::<Option<String>>()?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        let status = status.ok_or_else(|| de::Error::missing_field("status"))?;
+        if let (0, Some(raw)) = (status, raw_body) {
+            body = serde_json::from_str::<Option<T>>(raw.get()).map_err(de::Error::custom)?;
+        }
+
+        Ok(WithingsResponse {
+            status,
+            body,
+            error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Body {
+        value: u64,
+    }
+
+    fn meta() -> RequestMeta {
+        RequestMeta::new("https://wbsapi.withings.net/measure", "getmeas")
+    }
+
+    #[test]
+    fn test_decode_body() -> anyhow::Result<()> {
+        let bytes = serde_json::to_vec(&json!({"status": 0, "body": {"value": 1}}))?;
+        assert_eq!(decode::<Body>(meta(), &bytes)?, Body { value: 1 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_body_before_status() -> anyhow::Result<()> {
+        let bytes = br#"{"body": {"value": 1}, "status": 0}"#;
+        assert_eq!(decode::<Body>(meta(), bytes)?, Body { value: 1 });
+
+        let bytes = br#"{"body": {}, "status": 503}"#;
+        assert_matches!(
+            decode::<Body>(meta(), bytes),
+            Err(Error::Api(WithingsApiError::InvalidParams {
+                status: 503,
+                ..
+            }))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_error_status_skips_body() -> anyhow::Result<()> {
+        let bytes = serde_json::to_vec(&json!({
+            "status": 601,
+            "body": {"unexpected": true},
+            "error": "Too Many Requests"
+        }))?;
+        assert_matches!(
+            decode::<Body>(meta(), &bytes),
+            Err(Error::Api(WithingsApiError::TooManyRequests { error, .. })) => {
+                assert_eq!(error.as_deref(), Some("Too Many Requests"));
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_strict() -> anyhow::Result<()> {
+        let bytes = br#"{"status": 0, "body": {"value": 1}}"#;
+        assert_eq!(decode_strict::<Body>(meta(), bytes)?, Body { value: 1 });
+
+        let bytes = br#"{"status": 0, "body": {"value": 1, "extra": [{"a": 1}]}, "time": 1}"#;
+        assert_eq!(decode::<Body>(meta(), bytes)?, Body { value: 1 });
+        assert_matches!(
+            decode_strict::<Body>(meta(), bytes),
+            Err(Error::UnknownFields { fields, .. }) => {
+                assert_eq!(fields, vec!["body.extra", "time"]);
+            }
+        );
+
+        let bytes = br#"{"body": {"value": 1, "extra": 1}, "status": 0}"#;
+        assert_matches!(
+            decode_strict::<Body>(meta(), bytes),
+            Err(Error::UnknownFields { fields, .. }) => {
+                assert_eq!(fields, vec!["body.extra"]);
+            }
+        );
+
+        // The status error wins over unknown fields.
+        let bytes = br#"{"status": 601, "body": {}, "time": 1}"#;
+        assert_matches!(
+            decode_strict::<Body>(meta(), bytes),
+            Err(Error::Api(WithingsApiError::TooManyRequests { .. }))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        assert_matches!(
+            decode::<Body>(meta(), br#"{"body": {"value": 1}}"#),
+            Err(Error::Decode(_))
+        );
+        assert_matches!(
+            decode::<Body>(meta(), br#"{"status": 0}"#),
+            Err(Error::Decode(_))
+        );
+        assert_matches!(
+            decode::<Body>(meta(), br#"{"status": 0, "body": {"value": "1"}}"#),
+            Err(Error::Decode(_))
+        );
+        assert_matches!(decode::<Body>(meta(), b"not json"), Err(Error::Decode(_)));
+    }
+}
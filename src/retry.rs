@@ -0,0 +1,224 @@
+use std::time::Duration;
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+
+use crate::error::{handle_response_with, RequestMeta, WithingsApiError};
+use crate::rate_limit::RateLimiter;
+use crate::{Error, Result};
+
+/// Exponential backoff for transient failures: Withings "temporary" and
+/// "too many requests" body statuses, HTTP 429/5xx, timeouts and connection
+/// errors. A `Retry-After` on HTTP 429/503 or Withings status 601 replaces
+/// the backoff, capped at `max_backoff`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Randomize each backoff between half and the full delay.
+    pub jitter: bool,
+    /// Only retry requests that are safe to repeat, i.e. data reads. Token
+    /// requests consume their authorization code or refresh token.
+    pub idempotent_only: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: true,
+            idempotent_only: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Send every request once.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Delay before retrying after the given failed attempt (starting at 1).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .multiplier
+            .powi(attempt.saturating_sub(1).min(i32::MAX as u32) as i32);
+        // Clamp in seconds: the unclamped delay may not fit in a `Duration`.
+        let secs = (self.initial_backoff.as_secs_f64() * exp).min(self.max_backoff.as_secs_f64());
+        let delay = Duration::try_from_secs_f64(secs).unwrap_or(self.max_backoff);
+        if self.jitter {
+            delay / 2 + delay.mul_f64(rand::random::<f64>() / 2.0)
+        } else {
+            delay
+        }
+    }
+
+    fn should_retry(&self, attempt: u32, idempotent: bool) -> bool {
+        attempt < self.max_attempts && (idempotent || !self.idempotent_only)
+    }
+}
+
+/// Where a request goes through the client-side limits before being sent.
+pub(crate) struct Dispatch<'a> {
+    pub retry_policy: &'a RetryPolicy,
+    pub rate_limiter: Option<&'a RateLimiter>,
+    pub user_id: Option<u64>,
+    pub idempotent: bool,
+    pub strict: bool,
+}
+
+pub(crate) async fn send_with_retry<U: DeserializeOwned>(
+    dispatch: Dispatch<'_>,
+    meta: RequestMeta,
+    req: reqwest::RequestBuilder,
+) -> Result<U> {
+    let policy = dispatch.retry_policy;
+    let mut attempt = 1;
+    loop {
+        if let Some(limiter) = dispatch.rate_limiter {
+            limiter.acquire_for(&meta.action, dispatch.user_id).await;
+        }
+
+        let next = if policy.should_retry(attempt, dispatch.idempotent) {
+            req.try_clone()
+        } else {
+            None
+        };
+        let next = match next {
+            Some(next) => next,
+            None => return send(meta, req, dispatch.strict).await.0,
+        };
+
+        match send(meta.clone(), next, dispatch.strict).await {
+            (Err(e), retry_after) if e.is_retryable() => {
+                let delay = match retry_after {
+                    Some(delay) => delay.min(policy.max_backoff),
+                    None => policy.backoff(attempt),
+                };
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            (res, _) => return res,
+        }
+    }
+}
+
+/// Send a request, along with the `Retry-After` delay of a throttled response.
+async fn send<U: DeserializeOwned>(
+    meta: RequestMeta,
+    req: reqwest::RequestBuilder,
+    strict: bool,
+) -> (Result<U>, Option<Duration>) {
+    let res = match req.send().await {
+        Ok(res) => res,
+        Err(e) => return (Err(e.into()), None),
+    };
+    let retry_after = res
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after);
+
+    let res = handle_response_with(meta, res, strict).await;
+    let throttled = match &res {
+        Err(Error::HttpStatus { status, .. }) => {
+            *status == StatusCode::TOO_MANY_REQUESTS || *status == StatusCode::SERVICE_UNAVAILABLE
+        }
+        Err(Error::Api(WithingsApiError::TooManyRequests { .. })) => true,
+        _ => false,
+    };
+    (res, retry_after.filter(|_| throttled))
+}
+
+/// `Retry-After` in delay-seconds or HTTP-date form.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(std::time::SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_without_jitter() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            multiplier: 2.0,
+            jitter: false,
+            idempotent_only: true,
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff(4), Duration::from_millis(300));
+        assert_eq!(policy.backoff(1000), Duration::from_millis(300));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_backoff_with_jitter() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        for _ in 0..100 {
+            let delay = policy.backoff(2);
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_millis(200));
+            assert!(policy.backoff(1000) <= policy.max_backoff);
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let later = std::time::SystemTime::now() + Duration::from_secs(3600);
+        let delay = parse_retry_after(&httpdate::fmt_http_date(later)).unwrap();
+        assert!(delay > Duration::from_secs(3590) && delay <= Duration::from_secs(3600));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let policy = RetryPolicy::default();
+        assert!(policy.should_retry(1, true));
+        assert!(policy.should_retry(2, true));
+        assert!(!policy.should_retry(3, true));
+        assert!(!policy.should_retry(1, false));
+
+        let policy = RetryPolicy {
+            idempotent_only: false,
+            ..Default::default()
+        };
+        assert!(policy.should_retry(1, false));
+
+        assert!(!RetryPolicy::none().should_retry(1, true));
+    }
+}
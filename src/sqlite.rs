@@ -0,0 +1,883 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use crate::api::activity::Activity;
+use crate::api::measure::{Category, MeasureGroup, MeasureType};
+use crate::api::sleep::SleepSummary;
+use crate::api::user::Device;
+use crate::api::workout::Workout;
+use crate::backfill::{Checkpoint, CheckpointStore};
+use crate::sync::{Batch, Change, CursorStore, Record, RecordKey, Service, Sink};
+use crate::{Error, Result};
+
+/// Schema changes, applied in order. `PRAGMA user_version` holds how many
+/// have been applied; append new ones, never edit released ones.
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE measure_groups (
+        user_id INTEGER NOT NULL,
+        grpid INTEGER NOT NULL,
+        attrib INTEGER NOT NULL,
+        date INTEGER NOT NULL,
+        created INTEGER NOT NULL,
+        modified INTEGER,
+        category INTEGER NOT NULL,
+        deviceid TEXT,
+        hash_deviceid TEXT,
+        comment TEXT,
+        PRIMARY KEY (user_id, grpid)
+    );
+    CREATE INDEX measure_groups_date ON measure_groups (user_id, date);
+
+    -- A group can hold several measures of one type, e.g. one per body part
+    -- for segmental types, so measures are keyed by their position.
+    CREATE TABLE measures (
+        user_id INTEGER NOT NULL,
+        grpid INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        meastype INTEGER NOT NULL,
+        value INTEGER NOT NULL,
+        unit INTEGER NOT NULL,
+        real_value REAL NOT NULL,
+        algo INTEGER,
+        fm INTEGER,
+        PRIMARY KEY (user_id, grpid, position)
+    );
+
+    CREATE TABLE activities (
+        user_id INTEGER NOT NULL,
+        date TEXT NOT NULL,
+        timezone TEXT,
+        deviceid TEXT,
+        steps INTEGER,
+        distance REAL,
+        elevation REAL,
+        soft INTEGER,
+        moderate INTEGER,
+        intense INTEGER,
+        active INTEGER,
+        calories REAL,
+        totalcalories REAL,
+        hr_average INTEGER,
+        hr_min INTEGER,
+        hr_max INTEGER,
+        PRIMARY KEY (user_id, date)
+    );
+
+    CREATE TABLE sleep_summaries (
+        user_id INTEGER NOT NULL,
+        id INTEGER NOT NULL,
+        date TEXT NOT NULL,
+        timezone TEXT,
+        model_id INTEGER,
+        startdate INTEGER NOT NULL,
+        enddate INTEGER NOT NULL,
+        created INTEGER NOT NULL,
+        modified INTEGER NOT NULL,
+        total_sleep_time INTEGER,
+        total_timeinbed INTEGER,
+        deepsleepduration INTEGER,
+        lightsleepduration INTEGER,
+        remsleepduration INTEGER,
+        wakeupcount INTEGER,
+        sleep_score INTEGER,
+        sleep_efficiency REAL,
+        hr_average INTEGER,
+        rr_average INTEGER,
+        PRIMARY KEY (user_id, id)
+    );
+    CREATE INDEX sleep_summaries_date ON sleep_summaries (user_id, date);
+
+    CREATE TABLE workouts (
+        user_id INTEGER NOT NULL,
+        id INTEGER NOT NULL,
+        category INTEGER NOT NULL,
+        date TEXT NOT NULL,
+        timezone TEXT,
+        deviceid TEXT,
+        startdate INTEGER NOT NULL,
+        enddate INTEGER NOT NULL,
+        modified INTEGER,
+        calories REAL,
+        intensity INTEGER,
+        steps INTEGER,
+        distance REAL,
+        elevation REAL,
+        hr_average INTEGER,
+        PRIMARY KEY (user_id, id)
+    );
+
+    CREATE TABLE devices (
+        user_id INTEGER NOT NULL,
+        deviceid TEXT NOT NULL,
+        hash_deviceid TEXT,
+        type TEXT NOT NULL,
+        model TEXT NOT NULL,
+        model_id INTEGER,
+        battery TEXT,
+        timezone TEXT,
+        first_session_date INTEGER,
+        last_session_date INTEGER,
+        PRIMARY KEY (user_id, deviceid)
+    );
+
+    CREATE TABLE sync_cursors (
+        user_id INTEGER NOT NULL,
+        service TEXT NOT NULL,
+        cursor INTEGER NOT NULL,
+        PRIMARY KEY (user_id, service)
+    );
+
+    CREATE TABLE backfill_checkpoints (
+        user_id INTEGER NOT NULL,
+        service TEXT NOT NULL,
+        next INTEGER NOT NULL,
+        until INTEGER NOT NULL,
+        page_offset INTEGER,
+        PRIMARY KEY (user_id, service)
+    );
+"#];
+
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+/// A weight measure in kg.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Weight {
+    pub grpid: u64,
+    pub date: u64,
+    pub kg: f64,
+}
+
+/// Local copy of synced data, usable as the `Sink`, `CursorStore` and
+/// `CheckpointStore` of sync and backfill at once; clones share the
+/// connection.
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Open or create the database at `path`, migrating it to
+    /// `SCHEMA_VERSION`.
+    pub fn open(path: impl AsRef<Path>) -> Result<SqliteStore> {
+        SqliteStore::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<SqliteStore> {
+        SqliteStore::from_connection(Connection::open_in_memory()?)
+    }
+
+    pub fn from_connection(mut conn: Connection) -> Result<SqliteStore> {
+        migrate(&mut conn)?;
+        Ok(SqliteStore {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub async fn schema_version(&self) -> Result<usize> {
+        self.blocking(|conn| Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?))
+            .await
+    }
+
+    /// Store the devices returned by `getdevice`, keyed by `deviceid`.
+    pub async fn upsert_devices(&self, user_id: u64, devices: &[Device]) -> Result<()> {
+        let devices = devices.to_vec();
+        self.blocking(move |conn| {
+            let tx = conn.transaction()?;
+            for device in &devices {
+                tx.execute(
+                    "INSERT OR REPLACE INTO devices (user_id, deviceid, hash_deviceid, type, model,
+                        model_id, battery, timezone, first_session_date, last_session_date)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![
+                        user_id,
+                        device.deviceid,
+                        device.hash_deviceid,
+                        device.device_type,
+                        device.model,
+                        device.model_id,
+                        device.battery,
+                        device.timezone,
+                        device.first_session_date,
+                        device.last_session_date,
+                    ],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn devices(&self, user_id: u64) -> Result<Vec<Device>> {
+        self.blocking(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT deviceid, hash_deviceid, type, model, model_id, battery, timezone,
+                    first_session_date, last_session_date
+                 FROM devices WHERE user_id = ?1 ORDER BY deviceid",
+            )?;
+            let devices = stmt
+                .query_map([user_id], |row| {
+                    Ok(Device {
+                        deviceid: row.get(0)?,
+                        hash_deviceid: row.get(1)?,
+                        device_type: row.get(2)?,
+                        model: row.get(3)?,
+                        model_id: row.get(4)?,
+                        battery: row.get(5)?,
+                        timezone: row.get(6)?,
+                        first_session_date: row.get(7)?,
+                        last_session_date: row.get(8)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(devices)
+        })
+        .await
+    }
+
+    /// Most recent real (not objective) weight.
+    pub async fn latest_weight(&self, user_id: u64) -> Result<Option<Weight>> {
+        self.blocking(move |conn| {
+            Ok(conn
+                .query_row(
+                    &format!("{} ORDER BY g.date DESC LIMIT 1", WEIGHT_QUERY),
+                    params![user_id, MeasureType::Weight.code(), Category::Real.code()],
+                    weight_from_row,
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    /// Real weights with a `date` within `start..=end`, oldest first.
+    pub async fn weights_between(&self, user_id: u64, start: u64, end: u64) -> Result<Vec<Weight>> {
+        self.blocking(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "{} AND g.date BETWEEN ?4 AND ?5 ORDER BY g.date",
+                WEIGHT_QUERY
+            ))?;
+            let weights = stmt
+                .query_map(
+                    params![
+                        user_id,
+                        MeasureType::Weight.code(),
+                        Category::Real.code(),
+                        start,
+                        end
+                    ],
+                    weight_from_row,
+                )?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(weights)
+        })
+        .await
+    }
+
+    /// Direct access for tests; everything else goes through `blocking`.
+    #[cfg(test)]
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `f` on the blocking thread pool, so that callers don't stall the
+    /// executor while SQLite does I/O.
+    async fn blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut conn.lock().unwrap_or_else(|e| e.into_inner())))
+            .await
+            .map_err(|e| Error::Storage(Box::new(e)))?
+    }
+}
+
+const WEIGHT_QUERY: &str = "SELECT g.grpid, g.date, m.real_value
+     FROM measure_groups g
+     JOIN measures m ON m.user_id = g.user_id AND m.grpid = g.grpid
+     WHERE g.user_id = ?1 AND m.meastype = ?2 AND g.category = ?3";
+
+fn weight_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Weight> {
+    Ok(Weight {
+        grpid: row.get(0)?,
+        date: row.get(1)?,
+        kg: row.get(2)?,
+    })
+}
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction()?;
+    let version: usize = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::Storage(
+            format!(
+                "database schema version {} is newer than {}",
+                version, SCHEMA_VERSION
+            )
+            .into(),
+        ));
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn upsert_measure_group(tx: &Transaction<'_>, user_id: u64, grp: &MeasureGroup) -> Result<()> {
+    tx.execute(
+        "INSERT OR REPLACE INTO measure_groups (user_id, grpid, attrib, date, created, modified,
+            category, deviceid, hash_deviceid, comment)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            user_id,
+            grp.grpid,
+            grp.attrib,
+            grp.date,
+            grp.created,
+            grp.modified,
+            grp.category.code(),
+            grp.deviceid,
+            grp.hash_deviceid,
+            grp.comment,
+        ],
+    )?;
+    // Measures of a modified group may have been removed.
+    tx.execute(
+        "DELETE FROM measures WHERE user_id = ?1 AND grpid = ?2",
+        params![user_id, grp.grpid],
+    )?;
+    for (position, measure) in grp.measures.iter().enumerate() {
+        tx.execute(
+            "INSERT OR REPLACE INTO measures (user_id, grpid, position, meastype, value, unit,
+                real_value, algo, fm)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                user_id,
+                grp.grpid,
+                position,
+                measure.meastype.code(),
+                measure.value,
+                measure.unit,
+                measure.real_value(),
+                measure.algo,
+                measure.fm,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn upsert_activity(tx: &Transaction<'_>, user_id: u64, activity: &Activity) -> Result<()> {
+    tx.execute(
+        "INSERT OR REPLACE INTO activities (user_id, date, timezone, deviceid, steps, distance,
+            elevation, soft, moderate, intense, active, calories, totalcalories, hr_average,
+            hr_min, hr_max)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+        params![
+            user_id,
+            activity.date.to_string(),
+            activity.timezone,
+            activity.deviceid,
+            activity.steps,
+            activity.distance,
+            activity.elevation,
+            activity.soft,
+            activity.moderate,
+            activity.intense,
+            activity.active,
+            activity.calories,
+            activity.totalcalories,
+            activity.hr_average,
+            activity.hr_min,
+            activity.hr_max,
+        ],
+    )?;
+    Ok(())
+}
+
+fn upsert_sleep_summary(tx: &Transaction<'_>, user_id: u64, summary: &SleepSummary) -> Result<()> {
+    let data = &summary.data;
+    tx.execute(
+        "INSERT OR REPLACE INTO sleep_summaries (user_id, id, date, timezone, model_id, startdate,
+            enddate, created, modified, total_sleep_time, total_timeinbed, deepsleepduration,
+            lightsleepduration, remsleepduration, wakeupcount, sleep_score, sleep_efficiency,
+            hr_average, rr_average)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+            ?18, ?19)",
+        params![
+            user_id,
+            summary.id,
+            summary.date.to_string(),
+            summary.timezone,
+            summary.model_id,
+            summary.startdate,
+            summary.enddate,
+            summary.created,
+            summary.modified,
+            data.total_sleep_time,
+            data.total_timeinbed,
+            data.deepsleepduration,
+            data.lightsleepduration,
+            data.remsleepduration,
+            data.wakeupcount,
+            data.sleep_score,
+            data.sleep_efficiency,
+            data.hr_average,
+            data.rr_average,
+        ],
+    )?;
+    Ok(())
+}
+
+fn upsert_workout(tx: &Transaction<'_>, user_id: u64, workout: &Workout) -> Result<()> {
+    let data = &workout.data;
+    tx.execute(
+        "INSERT OR REPLACE INTO workouts (user_id, id, category, date, timezone, deviceid,
+            startdate, enddate, modified, calories, intensity, steps, distance, elevation,
+            hr_average)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        params![
+            user_id,
+            workout.id,
+            workout.category,
+            workout.date.to_string(),
+            workout.timezone,
+            workout.deviceid,
+            workout.startdate,
+            workout.enddate,
+            workout.modified,
+            data.calories,
+            data.intensity,
+            data.steps,
+            data.distance,
+            data.elevation,
+            data.hr_average,
+        ],
+    )?;
+    Ok(())
+}
+
+fn delete(tx: &Transaction<'_>, user_id: u64, key: &RecordKey) -> Result<()> {
+    match key {
+        RecordKey::Measure { grpid } => {
+            tx.execute(
+                "DELETE FROM measures WHERE user_id = ?1 AND grpid = ?2",
+                params![user_id, grpid],
+            )?;
+            tx.execute(
+                "DELETE FROM measure_groups WHERE user_id = ?1 AND grpid = ?2",
+                params![user_id, grpid],
+            )?;
+        }
+        RecordKey::Activity { date } => {
+            tx.execute(
+                "DELETE FROM activities WHERE user_id = ?1 AND date = ?2",
+                params![user_id, date.to_string()],
+            )?;
+        }
+        RecordKey::SleepSummary { id } => {
+            tx.execute(
+                "DELETE FROM sleep_summaries WHERE user_id = ?1 AND id = ?2",
+                params![user_id, id],
+            )?;
+        }
+        RecordKey::Workout { id } => {
+            tx.execute(
+                "DELETE FROM workouts WHERE user_id = ?1 AND id = ?2",
+                params![user_id, id],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+impl Sink for SqliteStore {
+    async fn write(&self, batch: Batch) -> Result<()> {
+        self.blocking(move |conn| {
+            let tx = conn.transaction()?;
+            for change in &batch.changes {
+                match change {
+                    Change::Upsert { record, .. } => match record.as_ref() {
+                        Record::Measure(grp) => upsert_measure_group(&tx, batch.user_id, grp)?,
+                        Record::Activity(activity) => {
+                            upsert_activity(&tx, batch.user_id, activity)?
+                        }
+                        Record::SleepSummary(summary) => {
+                            upsert_sleep_summary(&tx, batch.user_id, summary)?
+                        }
+                        Record::Workout(workout) => upsert_workout(&tx, batch.user_id, workout)?,
+                    },
+                    Change::Delete { key } => delete(&tx, batch.user_id, key)?,
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn known_groups(&self, user_id: u64, start: u64, end: u64) -> Result<Vec<u64>> {
+        self.blocking(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT grpid FROM measure_groups WHERE user_id = ?1 AND date BETWEEN ?2 AND ?3",
+            )?;
+            let grpids = stmt
+                .query_map(params![user_id, start, end], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(grpids)
+        })
+        .await
+    }
+}
+
+impl CursorStore for SqliteStore {
+    async fn load(&self, user_id: u64, service: Service) -> Result<Option<u64>> {
+        self.blocking(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT cursor FROM sync_cursors WHERE user_id = ?1 AND service = ?2",
+                    params![user_id, service.as_str()],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn save(&self, user_id: u64, service: Service, cursor: u64) -> Result<()> {
+        self.blocking(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO sync_cursors (user_id, service, cursor)
+                 VALUES (?1, ?2, ?3)",
+                params![user_id, service.as_str(), cursor],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl CheckpointStore for SqliteStore {
+    async fn load(&self, user_id: u64, service: Service) -> Result<Option<Checkpoint>> {
+        self.blocking(move |conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT next, until, page_offset FROM backfill_checkpoints
+                     WHERE user_id = ?1 AND service = ?2",
+                    params![user_id, service.as_str()],
+                    |row| {
+                        Ok(Checkpoint {
+                            next: row.get(0)?,
+                            until: row.get(1)?,
+                            offset: row.get(2)?,
+                        })
+                    },
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn save(&self, user_id: u64, service: Service, checkpoint: Checkpoint) -> Result<()> {
+        self.blocking(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO backfill_checkpoints (user_id, service, next, until,
+                    page_offset)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    user_id,
+                    service.as_str(),
+                    checkpoint.next,
+                    checkpoint.until,
+                    checkpoint.offset
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::measure::Measure;
+    use crate::api::workout::WorkoutData;
+    use crate::params::YmdDate;
+    use crate::sync::{ChangeKind, SyncEngine};
+    use crate::test_util::{api, group, mock};
+    use assert_matches::assert_matches;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn weight_group(grpid: u64, date: u64, grams: i64) -> MeasureGroup {
+        MeasureGroup {
+            grpid,
+            attrib: 0,
+            date,
+            created: date,
+            modified: None,
+            category: Category::Real,
+            deviceid: None,
+            hash_deviceid: None,
+            measures: vec![Measure {
+                value: grams,
+                meastype: MeasureType::Weight,
+                unit: -3,
+                algo: None,
+                fm: None,
+                apppfmid: None,
+                appliver: None,
+            }],
+            comment: None,
+        }
+    }
+
+    fn upsert(record: Record) -> Change {
+        Change::Upsert {
+            kind: ChangeKind::Inserted,
+            record: Box::new(record),
+        }
+    }
+
+    fn batch(service: Service, changes: Vec<Change>) -> Batch {
+        Batch {
+            user_id: 363,
+            service,
+            changes,
+        }
+    }
+
+    fn count(store: &SqliteStore, table: &str) -> u64 {
+        store
+            .conn()
+            .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_migrate() -> anyhow::Result<()> {
+        let mut conn = Connection::open_in_memory()?;
+        migrate(&mut conn)?;
+        migrate(&mut conn)?;
+        let store = SqliteStore::from_connection(conn)?;
+        assert_eq!(store.schema_version().await?, SCHEMA_VERSION);
+
+        let mut conn = Connection::open_in_memory()?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)?;
+        assert_matches!(migrate(&mut conn), Err(Error::Storage(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_is_idempotent() -> anyhow::Result<()> {
+        let store = SqliteStore::open_in_memory()?;
+        let changes = vec![
+            upsert(Record::Measure(weight_group(1, 1643969671, 80000))),
+            upsert(Record::Workout(Workout {
+                id: 7,
+                category: 1,
+                timezone: None,
+                model: None,
+                attrib: None,
+                startdate: 1643950800,
+                enddate: 1643953200,
+                date: YmdDate::new(2022, 2, 4),
+                deviceid: None,
+                modified: None,
+                data: WorkoutData {
+                    steps: Some(3120),
+                    ..Default::default()
+                },
+            })),
+        ];
+
+        store
+            .write(batch(Service::Measure, changes.clone()))
+            .await?;
+        store.write(batch(Service::Measure, changes)).await?;
+        assert_eq!(count(&store, "measure_groups"), 1);
+        assert_eq!(count(&store, "measures"), 1);
+        assert_eq!(count(&store, "workouts"), 1);
+
+        let mut modified = weight_group(1, 1643969671, 79500);
+        modified.measures.push(Measure {
+            meastype: MeasureType::FatRatio,
+            ..modified.measures[0].clone()
+        });
+        store
+            .write(batch(
+                Service::Measure,
+                vec![upsert(Record::Measure(modified))],
+            ))
+            .await?;
+        assert_eq!(count(&store, "measures"), 2);
+        assert_eq!(store.latest_weight(363).await?.map(|w| w.kg), Some(79.5));
+
+        store
+            .write(batch(
+                Service::Measure,
+                vec![Change::Delete {
+                    key: RecordKey::Measure { grpid: 1 },
+                }],
+            ))
+            .await?;
+        assert_eq!(count(&store, "measure_groups"), 0);
+        assert_eq!(count(&store, "measures"), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_repeated_measure_types() -> anyhow::Result<()> {
+        let store = SqliteStore::open_in_memory()?;
+        let mut grp = weight_group(1, 1643969671, 80000);
+        for (value, fm) in [(1040, 1), (1020, 2)] {
+            grp.measures.push(Measure {
+                value,
+                meastype: MeasureType::FatFreeMassSegments,
+                unit: -2,
+                algo: None,
+                fm: Some(fm),
+                apppfmid: None,
+                appliver: None,
+            });
+        }
+        store
+            .write(batch(Service::Measure, vec![upsert(Record::Measure(grp))]))
+            .await?;
+
+        let segments = store
+            .conn()
+            .prepare("SELECT real_value FROM measures WHERE meastype = ?1 ORDER BY position")?
+            .query_map([MeasureType::FatFreeMassSegments.code()], |row| {
+                row.get::<_, f64>(0)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        assert_eq!(segments, vec![10.4, 10.2]);
+        assert_eq!(count(&store, "measures"), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_weights() -> anyhow::Result<()> {
+        let store = SqliteStore::open_in_memory()?;
+        let mut objective = weight_group(4, 400, 70000);
+        objective.category = Category::UserObjective;
+        store
+            .write(batch(
+                Service::Measure,
+                vec![
+                    upsert(Record::Measure(weight_group(1, 100, 81000))),
+                    upsert(Record::Measure(weight_group(2, 200, 80500))),
+                    upsert(Record::Measure(weight_group(3, 300, 80000))),
+                    upsert(Record::Measure(objective)),
+                ],
+            ))
+            .await?;
+
+        assert_eq!(
+            store.latest_weight(363).await?,
+            Some(Weight {
+                grpid: 3,
+                date: 300,
+                kg: 80.0
+            })
+        );
+        assert_eq!(store.latest_weight(1).await?, None);
+        assert_eq!(
+            store
+                .weights_between(363, 100, 200)
+                .await?
+                .iter()
+                .map(|w| w.kg)
+                .collect::<Vec<_>>(),
+            vec![81.0, 80.5]
+        );
+        assert_eq!(store.known_groups(363, 150, 400).await?, vec![2, 3, 4]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cursors_checkpoints_and_devices() -> anyhow::Result<()> {
+        let store = SqliteStore::open_in_memory()?;
+
+        CursorStore::save(&store, 363, Service::Activity, 1644138861).await?;
+        CursorStore::save(&store, 363, Service::Activity, 1644138862).await?;
+        assert_eq!(
+            CursorStore::load(&store, 363, Service::Activity).await?,
+            Some(1644138862)
+        );
+        assert_eq!(
+            CursorStore::load(&store, 363, Service::Measure).await?,
+            None
+        );
+
+        let checkpoint = Checkpoint {
+            next: 100,
+            until: 200,
+            offset: Some(3),
+        };
+        CheckpointStore::save(&store, 363, Service::Workouts, checkpoint).await?;
+        assert_eq!(
+            CheckpointStore::load(&store, 363, Service::Workouts).await?,
+            Some(checkpoint)
+        );
+
+        let device = Device {
+            deviceid: "test_device".into(),
+            hash_deviceid: None,
+            device_type: "Scale".into(),
+            model: "Body+".into(),
+            model_id: Some(5),
+            battery: Some("high".into()),
+            timezone: Some("Asia/Tokyo".into()),
+            first_session_date: None,
+            last_session_date: Some(1643969717),
+        };
+        store
+            .upsert_devices(363, std::slice::from_ref(&device))
+            .await?;
+        store
+            .upsert_devices(363, std::slice::from_ref(&device))
+            .await?;
+        assert_eq!(store.devices(363).await?, vec![device]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_into_store() -> anyhow::Result<()> {
+        let path = "/sqlite_sync/measure";
+        let changed = mock(
+            path,
+            "action=getmeas&lastupdate=0",
+            json!({"updatetime": 2000, "measuregrps": [group(1, 1000)]}),
+        );
+        let window = mock(
+            path,
+            "action=getmeas&startdate=0&enddate=2000",
+            json!({"measuregrps": [group(1, 1000)]}),
+        );
+
+        let store = SqliteStore::open_in_memory()?;
+        SyncEngine::new(api("/sqlite_sync"), 363, store.clone(), store.clone())
+            .with_services(vec![Service::Measure])
+            .run()
+            .await?;
+
+        assert_eq!(store.latest_weight(363).await?.map(|w| w.kg), Some(80.0));
+        assert_eq!(
+            CursorStore::load(&store, 363, Service::Measure).await?,
+            Some(2000)
+        );
+        changed.assert();
+        window.assert();
+        Ok(())
+    }
+}
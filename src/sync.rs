@@ -0,0 +1,910 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::api::activity::Activity;
+use crate::api::cli::{
+    ApiCli, GetActivityRequest, GetMeasRequest, GetSleepSummaryRequest, GetWorkoutsRequest,
+};
+use crate::api::measure::MeasureGroup;
+use crate::api::sleep::SleepSummary;
+use crate::api::workout::Workout;
+use crate::params::YmdDate;
+use crate::Result;
+
+/// Withings data synced incrementally, each with its own cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Service {
+    Measure,
+    Activity,
+    SleepSummary,
+    Workouts,
+}
+
+impl Service {
+    pub const ALL: [Service; 4] = [
+        Service::Measure,
+        Service::Activity,
+        Service::SleepSummary,
+        Service::Workouts,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Service::Measure => "measure",
+            Service::Activity => "activity",
+            Service::SleepSummary => "sleep_summary",
+            Service::Workouts => "workouts",
+        }
+    }
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Measure(MeasureGroup),
+    Activity(Activity),
+    SleepSummary(SleepSummary),
+    Workout(Workout),
+}
+
+impl Record {
+    /// When Withings first stored the record, for records that carry it.
+    pub fn created(&self) -> Option<u64> {
+        match self {
+            Record::Measure(grp) => Some(grp.created),
+            Record::SleepSummary(summary) => Some(summary.created),
+            Record::Activity(_) | Record::Workout(_) => None,
+        }
+    }
+
+    /// Last change in Withings, for records that carry it.
+    pub fn modified(&self) -> Option<u64> {
+        match self {
+            Record::Measure(grp) => grp.modified,
+            Record::SleepSummary(summary) => Some(summary.modified),
+            Record::Workout(workout) => workout.modified,
+            Record::Activity(_) => None,
+        }
+    }
+
+    pub fn key(&self) -> RecordKey {
+        match self {
+            Record::Measure(grp) => RecordKey::Measure { grpid: grp.grpid },
+            Record::Activity(activity) => RecordKey::Activity {
+                date: activity.date,
+            },
+            Record::SleepSummary(summary) => RecordKey::SleepSummary { id: summary.id },
+            Record::Workout(workout) => RecordKey::Workout { id: workout.id },
+        }
+    }
+}
+
+/// Identity of a record within a user's data; a later change with the same
+/// key replaces the record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordKey {
+    Measure { grpid: u64 },
+    Activity { date: YmdDate },
+    SleepSummary { id: u64 },
+    Workout { id: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    Inserted,
+    Updated,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Store `record`, replacing any record with the same key.
+    Upsert {
+        kind: ChangeKind,
+        record: Box<Record>,
+    },
+    /// Remove the record with `key`, deleted in Withings.
+    Delete { key: RecordKey },
+}
+
+impl Change {
+    pub fn key(&self) -> RecordKey {
+        match self {
+            Change::Upsert { record, .. } => record.key(),
+            Change::Delete { key } => *key,
+        }
+    }
+}
+
+/// Changes of one page of a service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    pub user_id: u64,
+    pub service: Service,
+    pub changes: Vec<Change>,
+}
+
+/// Where the `lastupdate` cursor of each user and service is kept between
+/// runs.
+pub trait CursorStore {
+    fn load(
+        &self,
+        user_id: u64,
+        service: Service,
+    ) -> impl Future<Output = Result<Option<u64>>> + Send;
+
+    fn save(
+        &self,
+        user_id: u64,
+        service: Service,
+        cursor: u64,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Receiver of synced changes. Returning `Ok` acknowledges the batch; an
+/// error stops the run without advancing the cursor, so the same changes are
+/// fetched again next time.
+pub trait Sink {
+    fn write(&self, batch: Batch) -> impl Future<Output = Result<()>> + Send;
+
+    /// `grpid`s of the measure groups the sink holds with a `date` within
+    /// `start..=end`, used to detect deletions.
+    fn known_groups(
+        &self,
+        user_id: u64,
+        start: u64,
+        end: u64,
+    ) -> impl Future<Output = Result<Vec<u64>>> + Send;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryCursorStore {
+    cursors: Mutex<HashMap<(u64, Service), u64>>,
+}
+
+impl CursorStore for MemoryCursorStore {
+    async fn load(&self, user_id: u64, service: Service) -> Result<Option<u64>> {
+        Ok(self
+            .cursors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(user_id, service))
+            .copied())
+    }
+
+    async fn save(&self, user_id: u64, service: Service, cursor: u64) -> Result<()> {
+        self.cursors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((user_id, service), cursor);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncReport {
+    pub service: Service,
+    pub inserted: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub pages: usize,
+    /// Cursor saved at the end of the run.
+    pub cursor: u64,
+}
+
+/// Reconcile window of a new [`SyncEngine`], 30 days.
+pub const DEFAULT_RECONCILE_WINDOW: Duration = Duration::from_secs(30 * 86400);
+
+/// Fetches what changed since the last run of each service with
+/// `lastupdate`, page by page, and hands it to a [`Sink`].
+///
+/// The cursor of a service is saved once every page has been acknowledged.
+/// Records changed before the previous cursor but created after it are
+/// `Inserted`, others `Updated`; records without a creation time (activity,
+/// workouts) are `Inserted` on the first run and `Updated` afterwards.
+///
+/// `lastupdate` doesn't return deleted measure groups, so every measure run
+/// also lists the groups dated within the reconcile window,
+/// [`DEFAULT_RECONCILE_WINDOW`] unless set otherwise, and reports a `Delete`
+/// for each group [`Sink::known_groups`] holds that Withings no longer has.
+pub struct SyncEngine<C, S> {
+    api: ApiCli,
+    user_id: u64,
+    services: Vec<Service>,
+    reconcile_window: Option<Duration>,
+    cursors: C,
+    sink: S,
+}
+
+impl<C: CursorStore, S: Sink> SyncEngine<C, S> {
+    pub fn new(api: ApiCli, user_id: u64, cursors: C, sink: S) -> SyncEngine<C, S> {
+        SyncEngine {
+            api: api.with_user_id(user_id),
+            user_id,
+            services: Service::ALL.to_vec(),
+            reconcile_window: Some(DEFAULT_RECONCILE_WINDOW),
+            cursors,
+            sink,
+        }
+    }
+
+    pub fn with_services(mut self, services: Vec<Service>) -> SyncEngine<C, S> {
+        self.services = services;
+        self
+    }
+
+    /// `None` stops looking for deleted measure groups, which then stay in
+    /// the sink.
+    pub fn with_reconcile_window(mut self, window: Option<Duration>) -> SyncEngine<C, S> {
+        self.reconcile_window = window;
+        self
+    }
+
+    pub fn cursors(&self) -> &C {
+        &self.cursors
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Sync every selected service in turn, stopping at the first error.
+    pub async fn run(&self) -> Result<Vec<SyncReport>> {
+        let mut reports = Vec::with_capacity(self.services.len());
+        for &service in &self.services {
+            reports.push(self.sync(service).await?);
+        }
+        Ok(reports)
+    }
+
+    pub async fn sync(&self, service: Service) -> Result<SyncReport> {
+        let previous = self.cursors.load(self.user_id, service).await?;
+        let mut report = SyncReport {
+            service,
+            inserted: 0,
+            updated: 0,
+            deleted: 0,
+            pages: 0,
+            cursor: now(),
+        };
+
+        let mut offset = None;
+        loop {
+            let page = fetch(
+                &self.api,
+                service,
+                Query::Since(previous.unwrap_or(0)),
+                offset,
+            )
+            .await?;
+            match page.updatetime {
+                // Server time of the first page, immune to local clock skew.
+                Some(updatetime) if report.pages == 0 && updatetime > 0 => {
+                    report.cursor = updatetime
+                }
+                _ => {}
+            }
+            report.pages += 1;
+
+            let changes = page
+                .records
+                .into_iter()
+                .map(|record| Change::Upsert {
+                    kind: change_kind(&record, previous),
+                    record: Box::new(record),
+                })
+                .collect::<Vec<_>>();
+            if !changes.is_empty() {
+                let inserted = changes
+                    .iter()
+                    .filter(|c| {
+                        matches!(
+                            c,
+                            Change::Upsert {
+                                kind: ChangeKind::Inserted,
+                                ..
+                            }
+                        )
+                    })
+                    .count();
+                report.inserted += inserted;
+                report.updated += changes.len() - inserted;
+                self.sink
+                    .write(Batch {
+                        user_id: self.user_id,
+                        service,
+                        changes,
+                    })
+                    .await?;
+            }
+
+            if !page.more || offset == Some(page.offset) {
+                break;
+            }
+            offset = Some(page.offset);
+        }
+
+        if let (Service::Measure, Some(window)) = (service, self.reconcile_window) {
+            let end = report.cursor;
+            let start = end.saturating_sub(window.as_secs());
+            report.deleted = self.reconcile_measures(start, end).await?;
+        }
+
+        self.cursors
+            .save(self.user_id, service, report.cursor)
+            .await?;
+        Ok(report)
+    }
+
+    /// Report groups dated within `start..=end` the sink holds but Withings
+    /// doesn't anymore as deleted.
+    async fn reconcile_measures(&self, start: u64, end: u64) -> Result<usize> {
+        let mut present = HashSet::new();
+        let mut offset = None;
+        loop {
+            let body = self
+                .api
+                .get_meas(&GetMeasRequest {
+                    action: "getmeas".into(),
+                    startdate: Some(start),
+                    enddate: Some(end),
+                    offset,
+                    ..Default::default()
+                })
+                .await?;
+            present.extend(body.measuregrps.iter().map(|grp| grp.grpid));
+            if body.more == 0 || offset == Some(body.offset) {
+                break;
+            }
+            offset = Some(body.offset);
+        }
+
+        let changes = self
+            .sink
+            .known_groups(self.user_id, start, end)
+            .await?
+            .into_iter()
+            .filter(|grpid| !present.contains(grpid))
+            .map(|grpid| Change::Delete {
+                key: RecordKey::Measure { grpid },
+            })
+            .collect::<Vec<_>>();
+        let deleted = changes.len();
+        if deleted > 0 {
+            self.sink
+                .write(Batch {
+                    user_id: self.user_id,
+                    service: Service::Measure,
+                    changes,
+                })
+                .await?;
+        }
+        Ok(deleted)
+    }
+}
+
+fn change_kind(record: &Record, previous: Option<u64>) -> ChangeKind {
+    match (previous, record.created()) {
+        (None, _) => ChangeKind::Inserted,
+        (Some(cursor), Some(created)) if created >= cursor => ChangeKind::Inserted,
+        _ => ChangeKind::Updated,
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// What to fetch: changes since a `lastupdate`, or records dated within
+/// `start..end` in epoch seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Query {
+    Since(u64),
+    Window { start: u64, end: u64 },
+}
+
+impl Query {
+    fn lastupdate(&self) -> Option<u64> {
+        match self {
+            Query::Since(lastupdate) => Some(*lastupdate),
+            Query::Window { .. } => None,
+        }
+    }
+
+    /// Inclusive end of the window, as getmeas expects.
+    fn dates(&self) -> (Option<u64>, Option<u64>) {
+        match self {
+            Query::Since(_) => (None, None),
+            Query::Window { start, end } => (Some(*start), Some(end.saturating_sub(1))),
+        }
+    }
+
+    fn ymd_dates(&self) -> (Option<YmdDate>, Option<YmdDate>) {
+        let (start, end) = self.dates();
+        (
+            start.map(YmdDate::from_timestamp),
+            end.map(YmdDate::from_timestamp),
+        )
+    }
+}
+
+pub(crate) struct Page {
+    pub records: Vec<Record>,
+    pub more: bool,
+    pub offset: u64,
+    pub updatetime: Option<u64>,
+}
+
+pub(crate) async fn fetch(
+    api: &ApiCli,
+    service: Service,
+    query: Query,
+    offset: Option<u64>,
+) -> Result<Page> {
+    let lastupdate = query.lastupdate();
+    let (startdate, enddate) = query.dates();
+    let (startdateymd, enddateymd) = query.ymd_dates();
+    let page = match service {
+        Service::Measure => {
+            let body = api
+                .get_meas(&GetMeasRequest {
+                    action: "getmeas".into(),
+                    startdate,
+                    enddate,
+                    lastupdate,
+                    offset,
+                    ..Default::default()
+                })
+                .await?;
+            Page {
+                records: body.measuregrps.into_iter().map(Record::Measure).collect(),
+                more: body.more != 0,
+                offset: body.offset,
+                updatetime: Some(body.updatetime),
+            }
+        }
+        Service::Activity => {
+            let body = api
+                .get_activity(&GetActivityRequest {
+                    startdateymd,
+                    enddateymd,
+                    lastupdate,
+                    offset,
+                    ..Default::default()
+                })
+                .await?;
+            Page {
+                records: body.activities.into_iter().map(Record::Activity).collect(),
+                more: body.more,
+                offset: body.offset,
+                updatetime: None,
+            }
+        }
+        Service::SleepSummary => {
+            let body = api
+                .get_sleep_summary(&GetSleepSummaryRequest {
+                    startdateymd,
+                    enddateymd,
+                    lastupdate,
+                    offset,
+                    ..Default::default()
+                })
+                .await?;
+            Page {
+                records: body.series.into_iter().map(Record::SleepSummary).collect(),
+                more: body.more,
+                offset: body.offset,
+                updatetime: None,
+            }
+        }
+        Service::Workouts => {
+            let body = api
+                .get_workouts(&GetWorkoutsRequest {
+                    startdateymd,
+                    enddateymd,
+                    lastupdate,
+                    offset,
+                    ..Default::default()
+                })
+                .await?;
+            Page {
+                records: body.series.into_iter().map(Record::Workout).collect(),
+                more: body.more,
+                offset: body.offset,
+                updatetime: None,
+            }
+        }
+    };
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{api, group, mock};
+    use crate::Error;
+    use assert_matches::assert_matches;
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct CollectSink {
+        batches: Mutex<Vec<Batch>>,
+        fail: bool,
+    }
+
+    impl Sink for CollectSink {
+        async fn write(&self, batch: Batch) -> Result<()> {
+            if self.fail {
+                return Err(Error::Storage("sink is down".into()));
+            }
+            self.batches.lock().unwrap().push(batch);
+            Ok(())
+        }
+
+        async fn known_groups(&self, _user_id: u64, _start: u64, _end: u64) -> Result<Vec<u64>> {
+            Ok(Vec::new())
+        }
+    }
+
+    impl CollectSink {
+        fn kinds(&self) -> Vec<(Service, ChangeKind)> {
+            self.batches
+                .lock()
+                .unwrap()
+                .iter()
+                .flat_map(|b| {
+                    b.changes.iter().filter_map(move |c| match c {
+                        Change::Upsert { kind, .. } => Some((b.service, *kind)),
+                        Change::Delete { .. } => None,
+                    })
+                })
+                .collect()
+        }
+    }
+
+    /// Keeps measure groups by `grpid` like a downstream database would.
+    #[derive(Default)]
+    struct GroupStore {
+        groups: Mutex<HashMap<u64, MeasureGroup>>,
+    }
+
+    impl Sink for GroupStore {
+        async fn write(&self, batch: Batch) -> Result<()> {
+            let mut groups = self.groups.lock().unwrap();
+            for change in batch.changes {
+                match change {
+                    Change::Upsert { record, .. } => {
+                        if let Record::Measure(grp) = *record {
+                            groups.insert(grp.grpid, grp);
+                        }
+                    }
+                    Change::Delete {
+                        key: RecordKey::Measure { grpid },
+                    } => {
+                        groups.remove(&grpid);
+                    }
+                    Change::Delete { .. } => {}
+                }
+            }
+            Ok(())
+        }
+
+        async fn known_groups(&self, _user_id: u64, start: u64, end: u64) -> Result<Vec<u64>> {
+            Ok(self
+                .groups
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|grp| (start..=end).contains(&grp.date))
+                .map(|grp| grp.grpid)
+                .collect())
+        }
+    }
+
+    impl GroupStore {
+        fn grpids(&self) -> Vec<u64> {
+            let mut grpids = self
+                .groups
+                .lock()
+                .unwrap()
+                .keys()
+                .copied()
+                .collect::<Vec<_>>();
+            grpids.sort();
+            grpids
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_measure_pages_and_advances_cursor() -> anyhow::Result<()> {
+        let prefix = "/sync_measure";
+        let path = format!("{}/measure", prefix);
+        let first = mock(
+            &path,
+            "action=getmeas&lastupdate=0",
+            json!({"updatetime": 2000, "measuregrps": [group(1, 1000)], "more": 1, "offset": 1}),
+        );
+        let second = mock(
+            &path,
+            "action=getmeas&offset=1&lastupdate=0",
+            json!({"updatetime": 2001, "measuregrps": [group(2, 1500)], "more": 0, "offset": 0}),
+        );
+
+        let engine = SyncEngine::new(
+            api(prefix),
+            363,
+            MemoryCursorStore::default(),
+            CollectSink::default(),
+        )
+        .with_services(vec![Service::Measure])
+        .with_reconcile_window(None);
+
+        let reports = engine.run().await?;
+        assert_eq!(
+            reports,
+            vec![SyncReport {
+                service: Service::Measure,
+                inserted: 2,
+                updated: 0,
+                deleted: 0,
+                pages: 2,
+                cursor: 2000,
+            }]
+        );
+        assert_eq!(
+            engine.cursors().load(363, Service::Measure).await?,
+            Some(2000)
+        );
+        first.assert();
+        second.assert();
+
+        // Next run only asks for what changed since the cursor.
+        let changed = mock(
+            &path,
+            "action=getmeas&lastupdate=2000",
+            json!({"updatetime": 3000, "measuregrps": [group(1, 1000), group(3, 2500)]}),
+        );
+        let report = engine.sync(Service::Measure).await?;
+        assert_eq!((report.inserted, report.updated), (1, 1));
+        assert_eq!(
+            engine.sink().kinds(),
+            vec![
+                (Service::Measure, ChangeKind::Inserted),
+                (Service::Measure, ChangeKind::Inserted),
+                (Service::Measure, ChangeKind::Updated),
+                (Service::Measure, ChangeKind::Inserted),
+            ]
+        );
+        assert_eq!(
+            engine.cursors().load(363, Service::Measure).await?,
+            Some(3000)
+        );
+        changed.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_reconciles_deleted_and_modified_groups() -> anyhow::Result<()> {
+        let prefix = "/sync_reconcile";
+        let path = format!("{}/measure", prefix);
+        let day = 86400;
+        let now = 100 * day;
+        let first = mock(
+            &path,
+            "action=getmeas&lastupdate=0",
+            json!({
+                "updatetime": now,
+                "measuregrps": [group(1, now - 3 * day), group(2, now - 2 * day), group(3, now - 40 * day)]
+            }),
+        );
+        let window = format!(
+            "action=getmeas&startdate={}&enddate={}",
+            now - 30 * day,
+            now
+        );
+        let first_window = mock(
+            &path,
+            &window,
+            json!({"measuregrps": [group(1, now - 3 * day), group(2, now - 2 * day)]}),
+        );
+
+        let engine = SyncEngine::new(
+            api(prefix),
+            363,
+            MemoryCursorStore::default(),
+            GroupStore::default(),
+        )
+        .with_services(vec![Service::Measure])
+        .with_reconcile_window(Some(Duration::from_secs(30 * day)));
+        assert_eq!(engine.run().await?[0].deleted, 0);
+        assert_eq!(engine.sink().grpids(), vec![1, 2, 3]);
+        first.assert();
+        first_window.assert();
+
+        // Group 1 is edited and group 2 deleted in the app. Only the edit
+        // shows up with lastupdate.
+        let mut edited = group(1, now - 3 * day);
+        edited["modified"] = json!(now + 10);
+        edited["measures"][0]["value"] = json!(79500);
+        let changed = mock(
+            &path,
+            &format!("action=getmeas&lastupdate={}", now),
+            json!({"updatetime": now + 60, "measuregrps": [edited.clone()]}),
+        );
+        let window = format!(
+            "action=getmeas&startdate={}&enddate={}",
+            now + 60 - 30 * day,
+            now + 60
+        );
+        let second_window = mock(&path, &window, json!({"measuregrps": [edited]}));
+
+        let report = engine.sync(Service::Measure).await?;
+        assert_eq!((report.updated, report.deleted), (1, 1));
+        // Group 3 is older than the window and kept as is.
+        assert_eq!(engine.sink().grpids(), vec![1, 3]);
+        let grp = engine.sink().groups.lock().unwrap()[&1].clone();
+        assert_eq!(grp.modified, Some(now + 10));
+        assert_eq!(grp.measures[0].value, 79500);
+        changed.assert();
+        second_window.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_reports_deleted_groups_by_default() -> anyhow::Result<()> {
+        let prefix = "/sync_default_delete";
+        let path = format!("{}/measure", prefix);
+        let day = 86400;
+        let now = 100 * day;
+        let window = |end: u64| {
+            format!(
+                "action=getmeas&startdate={}&enddate={}",
+                end - 30 * day,
+                end
+            )
+        };
+        let first = mock(
+            &path,
+            "action=getmeas&lastupdate=0",
+            json!({"updatetime": now, "measuregrps": [group(1, now - day), group(2, now - day)]}),
+        );
+        let first_window = mock(
+            &path,
+            &window(now),
+            json!({"measuregrps": [group(1, now - day), group(2, now - day)]}),
+        );
+
+        let engine = SyncEngine::new(
+            api(prefix),
+            363,
+            MemoryCursorStore::default(),
+            GroupStore::default(),
+        )
+        .with_services(vec![Service::Measure]);
+        engine.run().await?;
+        assert_eq!(engine.sink().grpids(), vec![1, 2]);
+        first.assert();
+        first_window.assert();
+
+        // Group 2 is deleted in the app, which lastupdate doesn't show.
+        let changed = mock(
+            &path,
+            &format!("action=getmeas&lastupdate={}", now),
+            json!({"updatetime": now + 60, "measuregrps": []}),
+        );
+        let second_window = mock(
+            &path,
+            &window(now + 60),
+            json!({"measuregrps": [group(1, now - day)]}),
+        );
+        assert_eq!(engine.sync(Service::Measure).await?.deleted, 1);
+        assert_eq!(engine.sink().grpids(), vec![1]);
+        changed.assert();
+        second_window.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_keeps_cursor_when_sink_fails() -> anyhow::Result<()> {
+        let prefix = "/sync_sink_fails";
+        let path = format!("{}/measure", prefix);
+        let mock = mock(
+            &path,
+            "action=getmeas&lastupdate=0",
+            json!({"updatetime": 2000, "measuregrps": [group(1, 1000)]}),
+        );
+
+        let engine = SyncEngine::new(
+            api(prefix),
+            363,
+            MemoryCursorStore::default(),
+            CollectSink {
+                fail: true,
+                ..Default::default()
+            },
+        );
+        assert_matches!(engine.run().await, Err(Error::Storage(_)));
+        assert_eq!(engine.cursors().load(363, Service::Measure).await?, None);
+        mock.assert();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_v2_services() -> anyhow::Result<()> {
+        let prefix = "/sync_v2";
+        let activity = mock(
+            "/sync_v2/v2/measure",
+            "action=getactivity&lastupdate=1644000000",
+            json!({
+                "activities": [{"date": "2022-02-04", "steps": 8123}],
+                "more": false,
+                "offset": 0
+            }),
+        );
+        let sleep = mock(
+            "/sync_v2/v2/sleep",
+            "action=getsummary&lastupdate=0",
+            json!({
+                "series": [{
+                    "id": 1,
+                    "startdate": 1643899800,
+                    "enddate": 1643926500,
+                    "date": "2022-02-04",
+                    "created": 1643927120,
+                    "modified": 1643927120,
+                    "data": {"sleep_score": 81}
+                }],
+                "more": false,
+                "offset": 0
+            }),
+        );
+        let workouts = mock(
+            "/sync_v2/v2/measure",
+            "action=getworkouts&lastupdate=0",
+            json!({"series": [], "more": false, "offset": 0}),
+        );
+
+        let cursors = MemoryCursorStore::default();
+        cursors.save(363, Service::Activity, 1644000000).await?;
+        let engine = SyncEngine::new(api(prefix), 363, cursors, CollectSink::default())
+            .with_services(vec![
+                Service::Activity,
+                Service::SleepSummary,
+                Service::Workouts,
+            ]);
+
+        let before = now();
+        let reports = engine.run().await?;
+        assert_eq!(reports.len(), 3);
+        assert!(reports.iter().all(|r| r.cursor >= before));
+        assert_eq!(
+            engine.sink().kinds(),
+            vec![
+                (Service::Activity, ChangeKind::Updated),
+                (Service::SleepSummary, ChangeKind::Inserted),
+            ]
+        );
+        for service in [Service::Activity, Service::SleepSummary, Service::Workouts] {
+            assert!(engine.cursors().load(363, service).await?.unwrap() >= before);
+        }
+        activity.assert();
+        sleep.assert();
+        workouts.assert();
+
+        Ok(())
+    }
+}
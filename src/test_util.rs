@@ -0,0 +1,74 @@
+// Which helpers are used depends on the enabled features.
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+
+use crate::api::cli::ApiCli;
+use crate::api::measure::MeasureBody;
+
+/// Real measure group holding an 80 kg weight, created at `date`.
+pub fn group(grpid: u64, date: u64) -> Value {
+    measure_group(
+        grpid,
+        date,
+        json!([{"value": 80000, "type": 1, "unit": -3}]),
+    )
+}
+
+/// Real measure group of `measures`, created at `date`.
+pub fn measure_group(grpid: u64, date: u64, measures: Value) -> Value {
+    json!({
+        "grpid": grpid,
+        "attrib": 0,
+        "date": date,
+        "created": date,
+        "category": 1,
+        "measures": measures
+    })
+}
+
+/// Scale weigh-in of 80.125 kg on 2022-02-04T10:14:31Z, along with a
+/// measure type the crate doesn't know.
+pub fn weigh_in() -> Value {
+    json!({
+        "grpid": 1,
+        "attrib": 0,
+        "date": 1643969671,
+        "created": 1643969717,
+        "category": 1,
+        "deviceid": "cc50f3",
+        "measures": [
+            {"value": 80125, "type": 1, "unit": -3},
+            {"value": 42, "type": 999, "unit": 0}
+        ]
+    })
+}
+
+/// Weight objective of 75 kg.
+pub fn objective(grpid: u64, date: u64) -> Value {
+    let mut grp = measure_group(grpid, date, json!([{"value": 75, "type": 1, "unit": 0}]));
+    grp["category"] = json!(2);
+    grp
+}
+
+/// getmeas body of `groups`, in `timezone` if any.
+pub fn measure_body(timezone: Option<&str>, groups: Vec<Value>) -> MeasureBody {
+    serde_json::from_value(json!({"timezone": timezone, "measuregrps": groups})).unwrap()
+}
+
+/// Mock answering a POST to `path` with `body` by a successful response.
+pub fn mock(path: &str, body: &str, response: Value) -> mockito::Mock {
+    mockito::mock("POST", path)
+        .match_body(body)
+        .with_status(200)
+        .with_body(json!({"status": 0, "body": response}).to_string())
+        .create()
+}
+
+/// Client of the mock server, under `prefix` to keep tests apart.
+pub fn api(prefix: &str) -> ApiCli {
+    ApiCli::new(
+        "access_token".into(),
+        format!("{}{}", mockito::server_url(), prefix),
+    )
+}
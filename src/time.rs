@@ -0,0 +1,221 @@
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::api::measure::{GetMeasRequest, MeasureBody, MeasureGroup};
+use crate::params::YmdDate;
+
+/// Epoch seconds as sent by Withings. Dates before 1970 are clamped to 0.
+pub fn to_timestamp<Z: TimeZone>(at: &DateTime<Z>) -> u64 {
+    u64::try_from(at.timestamp()).unwrap_or(0)
+}
+
+/// Epoch seconds returned by Withings, in the given time zone.
+pub fn from_timestamp<Z: TimeZone>(secs: u64, tz: &Z) -> Option<DateTime<Z>> {
+    let secs = i64::try_from(secs).ok()?;
+    Some(DateTime::from_timestamp(secs, 0)?.with_timezone(tz))
+}
+
+/// Parse an IANA time zone such as the `timezone` of a response.
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    name.parse().ok()
+}
+
+/// First instant of `date` in `tz`: local midnight, or the end of the gap
+/// when the clocks skip midnight, as in America/Santiago or Asia/Beirut
+/// when daylight saving time starts.
+pub fn start_of_day(date: NaiveDate, tz: &Tz) -> Option<DateTime<Tz>> {
+    let midnight = date.and_hms_opt(0, 0, 0)?;
+    (0..24 * 60).find_map(|minutes| {
+        tz.from_local_datetime(&(midnight + chrono::Duration::minutes(minutes)))
+            .earliest()
+    })
+}
+
+impl From<NaiveDate> for YmdDate {
+    fn from(date: NaiveDate) -> YmdDate {
+        YmdDate::new(date.year(), date.month(), date.day())
+    }
+}
+
+impl TryFrom<YmdDate> for NaiveDate {
+    type Error = String;
+
+    fn try_from(date: YmdDate) -> Result<NaiveDate, String> {
+        NaiveDate::from_ymd_opt(date.year, date.month, date.day)
+            .ok_or_else(|| format!("invalid date: {}", date))
+    }
+}
+
+impl GetMeasRequest {
+    pub fn with_startdate<Z: TimeZone>(self, at: DateTime<Z>) -> GetMeasRequest {
+        GetMeasRequest {
+            startdate: Some(to_timestamp(&at)),
+            ..self
+        }
+    }
+
+    pub fn with_enddate<Z: TimeZone>(self, at: DateTime<Z>) -> GetMeasRequest {
+        GetMeasRequest {
+            enddate: Some(to_timestamp(&at)),
+            ..self
+        }
+    }
+
+    pub fn with_lastupdate<Z: TimeZone>(self, at: DateTime<Z>) -> GetMeasRequest {
+        GetMeasRequest {
+            lastupdate: Some(to_timestamp(&at)),
+            ..self
+        }
+    }
+
+    /// Measures taken on `date` as a local day in `tz`.
+    pub fn with_day(self, date: NaiveDate, tz: &Tz) -> GetMeasRequest {
+        let start = start_of_day(date, tz);
+        let end = date.succ_opt().and_then(|d| start_of_day(d, tz));
+        GetMeasRequest {
+            startdate: start.map(|at| to_timestamp(&at)),
+            enddate: end.map(|at| to_timestamp(&at).saturating_sub(1)),
+            ..self
+        }
+    }
+}
+
+impl MeasureBody {
+    /// `timezone` of the response, `None` if missing or unknown.
+    pub fn tz(&self) -> Option<Tz> {
+        self.timezone.as_deref().and_then(parse_timezone)
+    }
+
+    pub fn updated_at(&self) -> Option<DateTime<Utc>> {
+        from_timestamp(self.updatetime, &Utc)
+    }
+
+    /// Groups with their `date` localized to the response `timezone`,
+    /// falling back to UTC.
+    pub fn local_groups(&self) -> impl Iterator<Item = (DateTime<Tz>, &MeasureGroup)> {
+        let tz = self.tz().unwrap_or(Tz::UTC);
+        self.measuregrps
+            .iter()
+            .filter_map(move |grp| Some((grp.date_in(&tz)?, grp)))
+    }
+}
+
+impl MeasureGroup {
+    pub fn date_in<Z: TimeZone>(&self, tz: &Z) -> Option<DateTime<Z>> {
+        from_timestamp(self.date, tz)
+    }
+
+    pub fn created_in<Z: TimeZone>(&self, tz: &Z) -> Option<DateTime<Z>> {
+        from_timestamp(self.created, tz)
+    }
+
+    /// Calendar day the group was measured on in `tz`.
+    pub fn local_day(&self, tz: &Tz) -> Option<NaiveDate> {
+        self.date_in(tz).map(|at| at.date_naive())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn test_request_datetimes() -> anyhow::Result<()> {
+        let tokyo: Tz = "Asia/Tokyo".parse()?;
+        let req = GetMeasRequest {
+            action: "getmeas".into(),
+            ..Default::default()
+        }
+        .with_startdate(Utc.with_ymd_and_hms(2022, 2, 4, 0, 0, 0).unwrap())
+        .with_enddate(tokyo.with_ymd_and_hms(2022, 2, 5, 9, 0, 0).unwrap())
+        .with_lastupdate(Utc.with_ymd_and_hms(1969, 12, 31, 0, 0, 0).unwrap());
+
+        assert_eq!(req.startdate, Some(1643932800));
+        assert_eq!(req.enddate, Some(1644019200));
+        assert_eq!(req.lastupdate, Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_day() -> anyhow::Result<()> {
+        let tokyo: Tz = "Asia/Tokyo".parse()?;
+        let req = GetMeasRequest::default()
+            .with_day(NaiveDate::from_ymd_opt(2022, 2, 4).unwrap(), &tokyo);
+
+        // 2022-02-03T15:00:00Z ..= 2022-02-04T14:59:59Z
+        assert_eq!(req.startdate, Some(1643900400));
+        assert_eq!(req.enddate, Some(1643986799));
+
+        // Beirut skips from 23:59:59 to 01:00 when daylight saving time starts.
+        let beirut: Tz = "Asia/Beirut".parse()?;
+        let req = GetMeasRequest::default()
+            .with_day(NaiveDate::from_ymd_opt(2022, 3, 27).unwrap(), &beirut);
+        // 2022-03-26T22:00:00Z ..= 2022-03-27T20:59:59Z
+        assert_eq!(req.startdate, Some(1648332000));
+        assert_eq!(req.enddate, Some(1648414799));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ymd_date() {
+        let date = NaiveDate::from_ymd_opt(2022, 2, 4).unwrap();
+        assert_eq!(YmdDate::from(date), YmdDate::new(2022, 2, 4));
+        assert_eq!(NaiveDate::try_from(YmdDate::new(2022, 2, 4)), Ok(date));
+        assert!(NaiveDate::try_from(YmdDate::new(2022, 2, 30)).is_err());
+    }
+
+    #[test]
+    fn test_localize_response() -> anyhow::Result<()> {
+        let body: MeasureBody = serde_json::from_value(json!({
+            "updatetime": "1644138861",
+            "timezone": "Asia/Tokyo",
+            "measuregrps": [{
+                "grpid": 1,
+                "attrib": 0,
+                // 2022-02-04T16:30:00Z, already the 5th in Tokyo
+                "date": 1643992200,
+                "created": 1643992260,
+                "category": 1,
+                "measures": []
+            }]
+        }))?;
+
+        let tokyo: Tz = "Asia/Tokyo".parse()?;
+        assert_eq!(body.tz(), Some(tokyo));
+        assert_eq!(
+            body.updated_at(),
+            Some(Utc.with_ymd_and_hms(2022, 2, 6, 9, 14, 21).unwrap())
+        );
+
+        let (date, grp) = body.local_groups().next().unwrap();
+        assert_eq!(date, tokyo.with_ymd_and_hms(2022, 2, 5, 1, 30, 0).unwrap());
+        assert_eq!(grp.local_day(&tokyo), NaiveDate::from_ymd_opt(2022, 2, 5));
+        assert_eq!(grp.local_day(&Tz::UTC), NaiveDate::from_ymd_opt(2022, 2, 4));
+        assert_eq!(
+            grp.created_in(&Utc),
+            Some(Utc.with_ymd_and_hms(2022, 2, 4, 16, 31, 0).unwrap())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_timezone_falls_back_to_utc() -> anyhow::Result<()> {
+        let body: MeasureBody = serde_json::from_value(json!({
+            "timezone": "Mars/Olympus_Mons",
+            "measuregrps": [{
+                "grpid": 1,
+                "attrib": 0,
+                "date": 1643992200,
+                "created": 1643992260,
+                "category": 1,
+                "measures": []
+            }]
+        }))?;
+        assert_eq!(body.tz(), None);
+        let (date, _) = body.local_groups().next().unwrap();
+        assert_eq!(date.timezone(), Tz::UTC);
+        Ok(())
+    }
+}